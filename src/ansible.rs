@@ -0,0 +1,307 @@
+use crate::filters;
+use crate::inners::deep_merge;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use tera::{Result, Value};
+
+fn string_arg<'a>(name: &str, args: &'a HashMap<String, Value>, arg: &str, default: &'a str) -> Result<&'a str> {
+    match args.get(arg) {
+        Some(Value::String(value)) => Ok(value),
+        None => Ok(default),
+        _ => Err(format!("{}: Invalid type for arg {}, expected string", name, arg).into()),
+    }
+}
+
+fn list_arg<'a>(name: &str, args: &'a HashMap<String, Value>, arg: &str) -> Result<&'a Vec<Value>> {
+    if let Some(Value::Array(items)) = args.get(arg) {
+        return Ok(items);
+    } else {
+        return Err(format!("{}: Invalid type for arg {}, expected list", name, arg).into());
+    }
+}
+
+/// like to_json with pretty=true and 4 spaces of indent by default
+pub fn to_nice_json(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    let mut args = args.clone();
+    args.entry("indent".to_owned()).or_insert(Value::from(4));
+    return filters::to_json(piped_arg, &args);
+}
+
+/// objects of other override the piped one in order, nested objects are merged too with recursive=true
+pub fn combine(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if !piped_arg.is_object() {
+        return Err("combine: Invalid type, expected object".into());
+    }
+    let others = match args.get("other") {
+        Some(Value::Object(_)) => vec![args["other"].clone()],
+        Some(Value::Array(others)) if others.iter().all(Value::is_object) => others.clone(),
+        _ => return Err("combine: Invalid type for arg other, expected object or list of objects".into()),
+    };
+    let recursive = match args.get("recursive") {
+        Some(Value::Bool(recursive)) => *recursive,
+        None => false,
+        _ => return Err("combine: Invalid type for arg recursive, expected boolean".into()),
+    };
+    let mut combined = piped_arg.clone();
+    for other in others.iter() {
+        if recursive {
+            deep_merge(&mut combined, other);
+        } else if let (Value::Object(combined), Value::Object(other)) = (&mut combined, other) {
+            combined.extend(other.clone());
+        }
+    }
+    return Ok(combined);
+}
+
+pub fn dict2items(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::Object(object) = piped_arg {
+        let key_name = string_arg("dict2items", args, "key_name", "key")?;
+        let value_name = string_arg("dict2items", args, "value_name", "value")?;
+        let items = object
+            .iter()
+            .map(|(k, v)| {
+                let mut item = serde_json::Map::new();
+                item.insert(key_name.to_owned(), Value::String(k.clone()));
+                item.insert(value_name.to_owned(), v.clone());
+                Value::Object(item)
+            })
+            .collect();
+        return Ok(Value::Array(items));
+    } else {
+        return Err("dict2items: Invalid type, expected object".into());
+    }
+}
+
+pub fn items2dict(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::Array(items) = piped_arg {
+        let key_name = string_arg("items2dict", args, "key_name", "key")?;
+        let value_name = string_arg("items2dict", args, "value_name", "value")?;
+        let mut object = serde_json::Map::new();
+        for item in items.iter() {
+            let key = match item.get(key_name) {
+                Some(Value::String(key)) => key.clone(),
+                Some(key) if !key.is_null() => key.to_string(),
+                _ => return Err(format!("items2dict: item {} has no key {}", item, key_name).into()),
+            };
+            let value = item
+                .get(value_name)
+                .ok_or(format!("items2dict: item {} has no key {}", item, value_name))?;
+            object.insert(key, value.clone());
+        }
+        return Ok(Value::Object(object));
+    } else {
+        return Err("items2dict: Invalid type, expected list of objects".into());
+    }
+}
+
+pub fn mandatory(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if !piped_arg.is_null() {
+        return Ok(piped_arg.clone());
+    }
+    let msg = string_arg("mandatory", args, "msg", "mandatory: value is null")?;
+    return Err(msg.into());
+}
+
+/// the text after the last `/`, like python os.path.basename
+pub fn basename(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::String(path) = piped_arg {
+        let basename = path.rsplit('/').next().unwrap_or("");
+        return Ok(Value::String(basename.to_owned()));
+    } else {
+        return Err("basename: Invalid type, expected string".into());
+    }
+}
+
+/// the text before the last `/`, like python os.path.dirname
+pub fn dirname(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::String(path) = piped_arg {
+        let dirname = match path.rfind('/') {
+            Some(idx) => {
+                let dirname = path[..idx + 1].trim_end_matches('/');
+                if dirname.is_empty() {
+                    &path[..idx + 1]
+                } else {
+                    dirname
+                }
+            }
+            None => "",
+        };
+        return Ok(Value::String(dirname.to_owned()));
+    } else {
+        return Err("dirname: Invalid type, expected string".into());
+    }
+}
+
+/// [root, extension] with the dot in the extension, like python os.path.splitext
+pub fn splitext(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::String(path) = piped_arg {
+        let name_start = path.rfind('/').map(|idx| idx + 1).unwrap_or(0);
+        let name = &path[name_start..];
+        let dot = name.rfind('.').filter(|dot| name[..*dot].chars().any(|c| c != '.'));
+        let (root, ext) = match dot {
+            Some(dot) => path.split_at(name_start + dot),
+            None => (path.as_str(), ""),
+        };
+        return Ok(Value::Array(vec![Value::from(root), Value::from(ext)]));
+    } else {
+        return Err("splitext: Invalid type, expected string".into());
+    }
+}
+
+/// true for true, 1 and the strings yes, on, true, y and 1, false for anything else
+pub fn to_bool(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    let value = match piped_arg {
+        Value::Bool(value) => *value,
+        Value::Number(num) => num.as_f64() == Some(1.0),
+        Value::String(value) => ["yes", "on", "true", "y", "1"].contains(&value.trim().to_lowercase().as_str()),
+        _ => false,
+    };
+    return Ok(Value::Bool(value));
+}
+
+fn flatten_into(items: &[Value], levels: Option<u64>, out: &mut Vec<Value>) {
+    for item in items.iter() {
+        match item {
+            Value::Array(nested) if levels != Some(0) => flatten_into(nested, levels.map(|levels| levels - 1), out),
+            Value::Null => {}
+            _ => out.push(item.clone()),
+        }
+    }
+}
+
+/// flattens nested lists skipping nulls, only levels deep if given
+pub fn flatten(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::Array(items) = piped_arg {
+        let levels = match args.get("levels") {
+            Some(Value::Number(levels)) => {
+                Some(levels.as_u64().ok_or("flatten: Error levels is not unsigned integer")?)
+            }
+            None => None,
+            _ => return Err("flatten: Invalid type for arg levels, expected number".into()),
+        };
+        let mut flat = vec![];
+        flatten_into(items, levels, &mut flat);
+        return Ok(Value::Array(flat));
+    } else {
+        return Err("flatten: Invalid type, expected list".into());
+    }
+}
+
+/// unique items of the piped list that are not in other
+pub fn difference(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::Array(items) = piped_arg {
+        let other = list_arg("difference", args, "other")?;
+        let mut result: Vec<Value> = vec![];
+        for item in items.iter() {
+            if !other.contains(item) && !result.contains(item) {
+                result.push(item.clone());
+            }
+        }
+        return Ok(Value::Array(result));
+    } else {
+        return Err("difference: Invalid type, expected list".into());
+    }
+}
+
+/// pairs the items of the piped list with the ones of other, stops at the shortest
+pub fn zip(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::Array(items) = piped_arg {
+        let other = list_arg("zip", args, "other")?;
+        let pairs = items
+            .iter()
+            .zip(other.iter())
+            .map(|(a, b)| Value::Array(vec![a.clone(), b.clone()]))
+            .collect();
+        return Ok(Value::Array(pairs));
+    } else {
+        return Err("zip: Invalid type, expected list".into());
+    }
+}
+
+fn parse_network(value: &str) -> Option<(IpAddr, u8)> {
+    let (addr, prefix) = match value.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (value, None),
+    };
+    let addr: IpAddr = addr.trim().parse().ok()?;
+    let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix
+            .trim()
+            .parse::<u8>()
+            .ok()
+            .filter(|prefix| *prefix <= max_prefix)?,
+        None => max_prefix,
+    };
+    return Some((addr, prefix));
+}
+
+fn mask(addr: &IpAddr, prefix: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0))),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0))),
+    }
+}
+
+fn apply_mask(addr: &IpAddr, prefix: u8, host_bits: bool) -> IpAddr {
+    match (addr, mask(addr, prefix)) {
+        (IpAddr::V4(addr), IpAddr::V4(mask)) => {
+            let (addr, mask) = (u32::from(*addr), u32::from(mask));
+            IpAddr::V4(Ipv4Addr::from(if host_bits { addr | !mask } else { addr & mask }))
+        }
+        (IpAddr::V6(addr), IpAddr::V6(mask)) => {
+            let (addr, mask) = (u128::from(*addr), u128::from(mask));
+            IpAddr::V6(Ipv6Addr::from(if host_bits { addr | !mask } else { addr & mask }))
+        }
+        _ => *addr,
+    }
+}
+
+fn is_private(addr: &IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(addr) => addr.is_private() || addr.is_loopback() || addr.is_link_local(),
+        IpAddr::V6(addr) => addr.is_loopback() || (addr.segments()[0] & 0xfe00) == 0xfc00,
+    }
+}
+
+/// the query result for a valid address or network, false for anything else
+fn ipaddr_query(value: &Value, query: &str) -> Result<Value> {
+    let (addr, prefix) = match value.as_str().and_then(parse_network) {
+        Some(network) => network,
+        None => return Ok(Value::Bool(false)),
+    };
+    let result = match query {
+        "" => value.clone(),
+        "address" => Value::String(addr.to_string()),
+        "host" => Value::String(format!("{}/{}", addr, prefix)),
+        "prefix" => Value::from(prefix),
+        "netmask" => Value::String(mask(&addr, prefix).to_string()),
+        "network" => Value::String(apply_mask(&addr, prefix, false).to_string()),
+        "broadcast" if addr.is_ipv4() => Value::String(apply_mask(&addr, prefix, true).to_string()),
+        "broadcast" => Value::Bool(false),
+        "net" | "subnet" => Value::String(format!("{}/{}", apply_mask(&addr, prefix, false), prefix)),
+        "ipv4" if addr.is_ipv4() => value.clone(),
+        "ipv6" if addr.is_ipv6() => value.clone(),
+        "private" if is_private(&addr) => value.clone(),
+        "public" if !is_private(&addr) => value.clone(),
+        "ipv4" | "ipv6" | "private" | "public" => Value::Bool(false),
+        _ => return Err(format!("ipaddr: query {} not supported", query).into()),
+    };
+    return Ok(result);
+}
+
+/// validates and queries ip addresses and networks, lists keep only the items with a result
+pub fn ipaddr(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    let query = string_arg("ipaddr", args, "query", "")?;
+    if let Value::Array(items) = piped_arg {
+        let mut results = vec![];
+        for item in items.iter() {
+            let result = ipaddr_query(item, query)?;
+            if result != Value::Bool(false) {
+                results.push(result);
+            }
+        }
+        return Ok(Value::Array(results));
+    }
+    return ipaddr_query(piped_arg, query);
+}
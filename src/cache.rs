@@ -0,0 +1,98 @@
+use anyhow::{anyhow, Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, DirBuilder};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+use tera::Value;
+use uuid::Uuid;
+
+pub const DEFAULT_TTL: u64 = 3600;
+
+static MEMORY: OnceLock<Mutex<HashMap<String, Value>>> = OnceLock::new();
+static DISK: OnceLock<(PathBuf, Duration)> = OnceLock::new();
+
+fn memory() -> &'static Mutex<HashMap<String, Value>> {
+    MEMORY.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// enables the on disk cache, entries older than ttl are ignored,
+/// the dir is created only accessible by the user since outputs of commands can be secrets
+pub fn set_dir(dir: &str, ttl: Duration) -> Result<()> {
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .with_context(|| format!("Error creating cache dir {}", dir))?;
+    // an existing dir is not changed, entries others can write would be command outputs planted by them
+    let metadata = fs::metadata(dir).with_context(|| format!("Error reading cache dir {}", dir))?;
+    if metadata.uid() != unsafe { libc::geteuid() } {
+        return Err(anyhow!("Error cache dir {} is not owned by the current user", dir));
+    }
+    if metadata.mode() & 0o022 != 0 {
+        return Err(anyhow!("Error cache dir {} is writable by group or others, expected mode 0700", dir));
+    }
+    let _ = DISK.set((PathBuf::from(dir), ttl));
+    Ok(())
+}
+
+/// forgets the results cached in memory, called before each render
+pub fn clear() {
+    memory().lock().expect("cache lock poisoned").clear();
+}
+
+pub fn key(parts: &str) -> String {
+    format!("{:x}", Sha256::digest(parts.as_bytes()))
+}
+
+pub fn get(key: &str) -> Option<Value> {
+    if let Some(value) = memory().lock().expect("cache lock poisoned").get(key) {
+        return Some(value.clone());
+    }
+    let (dir, ttl) = DISK.get()?;
+    let path = dir.join(format!("{}.json", key));
+    let modified = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok()?;
+    let age = SystemTime::now().duration_since(modified).unwrap_or_default();
+    if age > *ttl {
+        return None;
+    }
+    let value = fs::read_to_string(&path).ok()?.parse::<Value>().ok()?;
+    memory()
+        .lock()
+        .expect("cache lock poisoned")
+        .insert(key.to_string(), value.clone());
+    Some(value)
+}
+
+pub fn set(key: &str, value: &Value) {
+    memory()
+        .lock()
+        .expect("cache lock poisoned")
+        .insert(key.to_string(), value.clone());
+    if let Some((dir, _)) = DISK.get() {
+        let path = dir.join(format!("{}.json", key));
+        if let Err(e) = write_entry(dir, &path, &value.to_string()) {
+            eprintln!("Error writing cache file {} : {}", path.display(), e);
+        }
+    }
+}
+
+/// writes a temporary file readable only by the user and renames it,
+/// so a concurrent render never reads a partially written entry
+fn write_entry(dir: &Path, path: &Path, contents: &str) -> std::io::Result<()> {
+    let tmp_path = dir.join(format!(".{}.tmp", Uuid::new_v4().simple()));
+    let result = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp_path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .and_then(|()| fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
@@ -0,0 +1,110 @@
+use crate::sandbox;
+use glob::Pattern;
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tera::{Result, Value};
+
+fn metadata(name: &str, piped_arg: &Value) -> Result<fs::Metadata> {
+    if let Value::String(path) = piped_arg {
+        let path = sandbox::check_path(name, path)?;
+        return fs::metadata(&path)
+            .map_err(|e| format!("{}: error reading metadata of {} : {}", name, path.display(), e).into());
+    } else {
+        return Err(format!("{}: Invalid type, expected string", name).into());
+    }
+}
+
+fn read_bytes(name: &str, piped_arg: &Value) -> Result<Vec<u8>> {
+    if let Value::String(path) = piped_arg {
+        let path = sandbox::check_path(name, path)?;
+        return fs::read(&path).map_err(|e| format!("{}: error reading file {} : {}", name, path.display(), e).into());
+    } else {
+        return Err(format!("{}: Invalid type, expected string", name).into());
+    }
+}
+
+/// size in bytes
+pub fn file_size(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    let metadata = metadata("file_size", piped_arg)?;
+    return Ok(Value::from(metadata.len()));
+}
+
+/// last modification time as seconds since the unix epoch
+pub fn file_mtime(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    let metadata = metadata("file_mtime", piped_arg)?;
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+        .ok_or("file_mtime: error reading modification time")?;
+    return Ok(Value::from(mtime.as_secs()));
+}
+
+/// permissions in octal like 0644
+pub fn file_mode(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    let metadata = metadata("file_mode", piped_arg)?;
+    return Ok(Value::String(format!("{:04o}", metadata.permissions().mode() & 0o7777)));
+}
+
+pub fn read_lines(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    let data = read_bytes("read_lines", piped_arg)?;
+    let data = String::from_utf8(data).map_err(|e| format!("read_lines: error decoding file : {}", e))?;
+    return Ok(Value::Array(data.lines().map(Value::from).collect()));
+}
+
+fn walk(dir: &Path, recursive: bool, pattern: Option<&Pattern>, out: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("list_dir: error reading dir {} : {}", dir.display(), e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("list_dir: error reading dir {} : {}", dir.display(), e))?;
+        let path = entry.path();
+        if !sandbox::is_allowed(&format!("{}", path.display())) {
+            continue;
+        }
+        let matches = pattern
+            .map(|pattern| pattern.matches(&entry.file_name().to_string_lossy()))
+            .unwrap_or(true);
+        // symlinks to dirs are listed but not followed so links can't make a loop
+        let is_dir = entry.file_type().map(|file_type| file_type.is_dir()).unwrap_or(false);
+        if matches {
+            out.push(path.clone());
+        }
+        if recursive && is_dir {
+            walk(&path, recursive, pattern, out)?;
+        }
+    }
+    return Ok(());
+}
+
+/// sorted paths of the entries of the dir, of its subdirs too with recursive=true,
+/// pattern is a glob that the entry names have to match
+pub fn list_dir(args: &HashMap<String, Value>) -> Result<Value> {
+    let path = if let Some(Value::String(path)) = args.get("path") {
+        sandbox::check_path("list_dir", path)?
+    } else {
+        return Err("list_dir: Invalid type for arg path, expected string".into());
+    };
+    let recursive = match args.get("recursive") {
+        Some(Value::Bool(recursive)) => *recursive,
+        None => false,
+        _ => return Err("list_dir: Invalid type for arg recursive, expected boolean".into()),
+    };
+    let pattern = match args.get("pattern") {
+        Some(Value::String(pattern)) => {
+            Some(Pattern::new(pattern).map_err(|e| format!("list_dir: error in pattern {} : {}", pattern, e))?)
+        }
+        None => None,
+        _ => return Err("list_dir: Invalid type for arg pattern, expected string".into()),
+    };
+    let mut paths = vec![];
+    walk(&path, recursive, pattern.as_ref(), &mut paths)?;
+    paths.sort();
+    return Ok(Value::Array(
+        paths
+            .iter()
+            .map(|path| Value::String(format!("{}", path.display())))
+            .collect(),
+    ));
+}
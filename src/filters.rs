@@ -1,9 +1,18 @@
-use crate::inners::exec_cmd;
+use crate::formats;
+use crate::inners::{exec_argv, exec_cmd, indent_lines};
+use crate::query::Query;
+use crate::sandbox;
 use base64;
 use glob::glob;
+use hmac::{Hmac, Mac};
+use md5::Md5;
+use percent_encoding::{percent_decode_str, percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use regex::Regex;
+use serde::Serialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
 use std::process::Command;
 use tera::{Result, Value};
 use anyhow::Context;
@@ -28,7 +37,7 @@ pub fn bash(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
         .arg("-c")
         .arg(format!("echo \"$__data\" | {}", command));
 
-    return exec_cmd(&mut bash_cmd, command, &args);
+    return exec_cmd("bash", &mut bash_cmd, command, &args);
 }
 
 pub fn sed(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
@@ -51,7 +60,7 @@ pub fn sed(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
         .arg("-c")
         .arg(format!("echo \"$__data\" | {}", command));
 
-    return exec_cmd(&mut bash_cmd, &command, &args);
+    return exec_cmd("sed", &mut bash_cmd, &command, &args);
 }
 
 pub fn file_glob(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
@@ -64,6 +73,7 @@ pub fn file_glob(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value>
         };
         for entry in paths {
             match entry {
+                Ok(path) if !sandbox::is_allowed(&format!("{}", path.display())) => continue,
                 Ok(path) => files_matched.push(Value::String(format!("{}", path.display()))),
                 Err(e) => println!("{:?}", e),
             }
@@ -76,6 +86,7 @@ pub fn file_glob(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value>
 
 pub fn read_file(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
     if let Value::String(path) = piped_arg {
+        let path = sandbox::check_path("read_file", path)?;
         match fs::read_to_string(path) {
             Ok(contents) => return Ok(Value::String(contents)),
             Err(e) => return Err(format!("read_file: error reading file : {:?}", e).into()),
@@ -85,79 +96,126 @@ pub fn read_file(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value>
     }
 }
 
-pub fn file_name(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
-    if let Value::String(path) = piped_arg {
-        let path = Path::new(&path);
-        let file_name = match path.file_name() {
-            Some(file_name) => file_name,
-            None => return Err(format!("file_name: error extracting filename : path is root, no filename").into()),
-        };
-
-        match file_name.to_str() {
-            Some(file_name) => return Ok(Value::String(file_name.to_string())),
-            None => return Err(format!("file_name: error decoding filename").into()),
-        }
+pub fn strip_line_breaks(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::String(lines) = piped_arg {
+        return Ok(Value::String(lines.replace("\n", "")));
     } else {
-        return Err("file_name: Invalid type, expected string".into());
+        return Err("strip_line_breaks: Invalid type, expected string".into());
     }
 }
 
-pub fn file_dir(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
-    if let Value::String(path) = piped_arg {
-        let path = Path::new(&path);
-        let file_name = match path.parent() {
-            Some(file_name) => file_name,
-            None => return Err(format!("file_dir: error extracting filename : path is root, no filename").into()),
-        };
+/// strings are encoded as utf8, lists of numbers as raw bytes
+fn bytes_arg(name: &str, piped_arg: &Value) -> Result<Vec<u8>> {
+    match piped_arg {
+        Value::String(data) => Ok(data.as_bytes().to_vec()),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| item.as_u64().filter(|byte| *byte <= 255).map(|byte| byte as u8))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or(format!("{}: Invalid type, expected string or list of bytes", name).into()),
+        _ => Err(format!("{}: Invalid type, expected string or list of bytes", name).into()),
+    }
+}
 
-        match file_name.to_str() {
-            Some(file_name) => return Ok(Value::String(file_name.to_string())),
-            None => return Err(format!("file_dir: error decoding filename").into()),
-        }
+fn flag_arg(name: &str, args: &HashMap<String, Value>, arg: &str, default: bool) -> Result<bool> {
+    match args.get(arg) {
+        Some(Value::Bool(value)) => Ok(*value),
+        None => Ok(default),
+        _ => Err(format!("{}: Invalid type for arg {}, expected boolean", name, arg).into()),
+    }
+}
+
+/// decoded bytes that are not utf8 fail unless lossy=true replaces them with U+FFFD
+fn decoded_string(name: &str, decoded_data: Vec<u8>, args: &HashMap<String, Value>) -> Result<Value> {
+    if flag_arg(name, args, "lossy", false)? {
+        return Ok(Value::String(String::from_utf8_lossy(&decoded_data).into_owned()));
+    }
+    return String::from_utf8(decoded_data)
+        .map(Value::String)
+        .map_err(|e| format!("{}: utf8 decoding error, use lossy=true to replace invalid bytes : {}", name, e).into());
+}
+
+fn b64_config(name: &str, args: &HashMap<String, Value>) -> Result<base64::Config> {
+    let char_set = if flag_arg(name, args, "url_safe", false)? {
+        base64::CharacterSet::UrlSafe
+    } else {
+        base64::CharacterSet::Standard
+    };
+    return Ok(base64::Config::new(char_set, flag_arg(name, args, "pad", true)?));
+}
+
+pub fn b64encode(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    let data = bytes_arg("b64encode", piped_arg)?;
+    let encoded_data = base64::encode_config(&data, b64_config("b64encode", args)?);
+    return Ok(Value::String(encoded_data));
+}
+
+pub fn b64encode_file(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::String(path) = piped_arg {
+        let path = sandbox::check_path("b64encode_file", path)?;
+        let data = fs::read(&path).map_err(|e| format!("b64encode_file: error reading file : {:?}", e))?;
+        let encoded_data = base64::encode_config(&data, b64_config("b64encode_file", args)?);
+        return Ok(Value::String(encoded_data));
     } else {
-        return Err("file_dir: Invalid type, expected string".into());
+        return Err("b64encode_file: Invalid type, expected string".into());
     }
 }
 
-pub fn strip_line_breaks(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
-    if let Value::String(lines) = piped_arg {
-        return Ok(Value::String(lines.replace("\n", "")));
+pub fn b64decode(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::String(data) = piped_arg {
+        let config = b64_config("b64decode", args)?;
+        let decoded_data = base64::decode_config(data.trim_end_matches('='), config)
+            .map_err(|e| format!("b64decode: decoding error : {}", e))?;
+        return decoded_string("b64decode", decoded_data, args);
     } else {
-        return Err("strip_line_breaks: Invalid type, expected string".into());
+        return Err("b64decode: Invalid type, expected string".into());
     }
 }
 
-pub fn remove_extension(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
-    if let Value::String(filename) = piped_arg {
-        let mut parts: Vec<_> = filename.split('.').collect();
-        parts.pop();
-        let filename = parts.join(".");
-        return Ok(Value::String(filename));
+pub fn hex_encode(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    let data = bytes_arg("hex_encode", piped_arg)?;
+    return Ok(Value::String(hex::encode(data)));
+}
+
+pub fn hex_decode(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::String(data) = piped_arg {
+        let decoded_data = hex::decode(data.trim()).map_err(|e| format!("hex_decode: decoding error : {}", e))?;
+        return decoded_string("hex_decode", decoded_data, args);
     } else {
-        return Err("remove_extension: Invalid type, expected string".into());
+        return Err("hex_decode: Invalid type, expected string".into());
     }
 }
 
-pub fn b64encode(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+pub fn b32encode(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    let data = bytes_arg("b32encode", piped_arg)?;
+    let padding = flag_arg("b32encode", args, "pad", true)?;
+    return Ok(Value::String(base32::encode(base32::Alphabet::RFC4648 { padding }, &data)));
+}
+
+pub fn b32decode(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
     if let Value::String(data) = piped_arg {
-        let encoded_data = base64::encode(&data);
-        return Ok(Value::String(encoded_data));
+        let decoded_data = base32::decode(base32::Alphabet::RFC4648 { padding: false }, data.trim_end_matches('='))
+            .ok_or("b32decode: decoding error : invalid base32")?;
+        return decoded_string("b32decode", decoded_data, args);
     } else {
-        return Err("b64encode: Invalid type, expected string".into());
+        return Err("b32decode: Invalid type, expected string".into());
     }
 }
 
-pub fn b64decode(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+/// everything except the RFC 3986 unreserved characters is percent encoded
+const URL_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+pub fn url_encode(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    let data = bytes_arg("url_encode", piped_arg)?;
+    return Ok(Value::String(percent_encode(&data, URL_ENCODE_SET).to_string()));
+}
+
+pub fn url_decode(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
     if let Value::String(data) = piped_arg {
-        return base64::decode(&data)
-            .map_err(|e| format!("b64decode: decoding error : {}", e).into())
-            .and_then(|decoded_data| {
-                String::from_utf8(decoded_data)
-                    .map(Value::String)
-                    .map_err(|e| format!("b64decode: utf8 decoding error : {}", e).into())
-            });
+        let decoded_data: Vec<u8> = percent_decode_str(data).collect();
+        return decoded_string("url_decode", decoded_data, args);
     } else {
-        return Err("b64decode: Invalid type, expected string".into());
+        return Err("url_decode: Invalid type, expected string".into());
     }
 }
 
@@ -176,3 +234,384 @@ pub fn from_json(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value>
         return Err("from_json: Invalid type, expected string".into());
     }
 }
+
+pub fn query(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    let expr = if let Some(Value::String(expr)) = args.get("expr") {
+        expr
+    } else {
+        return Err("query: Invalid type for arg expr, expected string".into());
+    };
+    let query = Query::parse(expr).map_err(|e| format!("query: {}", e))?;
+    return query.run_to_value(piped_arg).map_err(|e| format!("query: {}", e).into());
+}
+
+pub fn exec(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    let data = if let Value::String(data) = piped_arg {
+        data
+    } else {
+        return Err("exec: Invalid type, expected string".into());
+    };
+    return exec_argv("exec", args, Some(data));
+}
+
+fn regex_arg(name: &str, args: &HashMap<String, Value>) -> Result<Regex> {
+    let pattern = if let Some(Value::String(pattern)) = args.get("pattern") {
+        pattern
+    } else {
+        return Err(format!("{}: Invalid type for arg pattern, expected string", name).into());
+    };
+    return Regex::new(pattern).map_err(|e| format!("{}: error in regex {} : {}", name, pattern, e).into());
+}
+
+pub fn regex_replace(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    let data = if let Value::String(data) = piped_arg {
+        data
+    } else {
+        return Err("regex_replace: Invalid type, expected string".into());
+    };
+    let regex = regex_arg("regex_replace", args)?;
+    let replacement = match args.get("replacement") {
+        Some(Value::String(replacement)) => replacement.as_str(),
+        None => "",
+        _ => return Err("regex_replace: Invalid type for arg replacement, expected string".into()),
+    };
+    let count = match args.get("count") {
+        Some(Value::Number(count)) => count
+            .as_u64()
+            .ok_or("regex_replace: Error count is not unsigned integer")? as usize,
+        None => 0,
+        _ => return Err("regex_replace: Invalid type for arg count, expected number".into()),
+    };
+    return Ok(Value::String(regex.replacen(data, count, replacement).into_owned()));
+}
+
+pub fn regex_match(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::String(data) = piped_arg {
+        let regex = regex_arg("regex_match", args)?;
+        return Ok(Value::Bool(regex.is_match(data)));
+    } else {
+        return Err("regex_match: Invalid type, expected string".into());
+    }
+}
+
+pub fn regex_find_all(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::String(data) = piped_arg {
+        let regex = regex_arg("regex_find_all", args)?;
+        let matches = regex
+            .find_iter(data)
+            .map(|found| Value::String(found.as_str().to_string()))
+            .collect();
+        return Ok(Value::Array(matches));
+    } else {
+        return Err("regex_find_all: Invalid type, expected string".into());
+    }
+}
+
+pub fn regex_captures(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::String(data) = piped_arg {
+        let regex = regex_arg("regex_captures", args)?;
+        let captures = match regex.captures(data) {
+            Some(captures) => captures,
+            None => return Ok(Value::Null),
+        };
+        let mut groups = serde_json::Map::new();
+        for (idx, name) in regex.capture_names().enumerate() {
+            let group = captures
+                .get(idx)
+                .map(|group| Value::String(group.as_str().to_string()))
+                .unwrap_or(Value::Null);
+            if let Some(name) = name {
+                groups.insert(name.to_string(), group.clone());
+            }
+            groups.insert(idx.to_string(), group);
+        }
+        return Ok(Value::Object(groups));
+    } else {
+        return Err("regex_captures: Invalid type, expected string".into());
+    }
+}
+
+fn hex_digest(name: &str, algo: &str, data: &[u8]) -> Result<String> {
+    let digest = match algo {
+        "sha256" => format!("{:x}", Sha256::digest(data)),
+        "sha512" => format!("{:x}", Sha512::digest(data)),
+        "sha1" => format!("{:x}", Sha1::digest(data)),
+        "md5" => format!("{:x}", Md5::digest(data)),
+        "blake3" => blake3::hash(data).to_hex().to_string(),
+        "crc32" => format!("{:08x}", crc32fast::hash(data)),
+        _ => {
+            return Err(format!(
+                "{}: algorithm {} not supported, expected sha256, sha512, sha1, md5, blake3 or crc32",
+                name, algo
+            )
+            .into())
+        }
+    };
+    return Ok(digest);
+}
+
+fn hash(name: &str, algo: &str, piped_arg: &Value) -> Result<Value> {
+    if let Value::String(data) = piped_arg {
+        return hex_digest(name, algo, data.as_bytes()).map(Value::String);
+    } else {
+        return Err(format!("{}: Invalid type, expected string", name).into());
+    }
+}
+
+pub fn sha256(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    return hash("sha256", "sha256", piped_arg);
+}
+
+pub fn sha512(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    return hash("sha512", "sha512", piped_arg);
+}
+
+pub fn sha1(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    return hash("sha1", "sha1", piped_arg);
+}
+
+pub fn md5(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    return hash("md5", "md5", piped_arg);
+}
+
+pub fn blake3(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    return hash("blake3", "blake3", piped_arg);
+}
+
+pub fn crc32(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    return hash("crc32", "crc32", piped_arg);
+}
+
+fn algo_arg<'a>(name: &str, args: &'a HashMap<String, Value>) -> Result<&'a str> {
+    match args.get("algo") {
+        Some(Value::String(algo)) => Ok(algo),
+        None => Ok("sha256"),
+        _ => Err(format!("{}: Invalid type for arg algo, expected string", name).into()),
+    }
+}
+
+pub fn hmac(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    let data = if let Value::String(data) = piped_arg {
+        data
+    } else {
+        return Err("hmac: Invalid type, expected string".into());
+    };
+    let key = if let Some(Value::String(key)) = args.get("key") {
+        key
+    } else {
+        return Err("hmac: Invalid type for arg key, expected string".into());
+    };
+    let digest = match algo_arg("hmac", args)? {
+        "sha256" => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).map_err(|e| format!("hmac: {}", e))?;
+            mac.update(data.as_bytes());
+            format!("{:x}", mac.finalize().into_bytes())
+        }
+        "sha512" => {
+            let mut mac = Hmac::<Sha512>::new_from_slice(key.as_bytes()).map_err(|e| format!("hmac: {}", e))?;
+            mac.update(data.as_bytes());
+            format!("{:x}", mac.finalize().into_bytes())
+        }
+        "sha1" => {
+            let mut mac = Hmac::<Sha1>::new_from_slice(key.as_bytes()).map_err(|e| format!("hmac: {}", e))?;
+            mac.update(data.as_bytes());
+            format!("{:x}", mac.finalize().into_bytes())
+        }
+        algo => {
+            return Err(format!("hmac: algorithm {} not supported, expected sha256, sha512 or sha1", algo).into())
+        }
+    };
+    return Ok(Value::String(digest));
+}
+
+pub fn hash_file(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::String(path) = piped_arg {
+        let path = sandbox::check_path("hash_file", path)?;
+        let data = fs::read(&path).map_err(|e| format!("hash_file: error reading file : {:?}", e))?;
+        return hex_digest("hash_file", algo_arg("hash_file", args)?, &data).map(Value::String);
+    } else {
+        return Err("hash_file: Invalid type, expected string".into());
+    }
+}
+
+fn serialize(name: &str, format: &str, piped_arg: &Value) -> Result<Value> {
+    return formats::serialize(piped_arg, format)
+        .map(Value::String)
+        .map_err(|e| format!("{}: {:#}", name, e).into());
+}
+
+pub fn to_json(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    let indent = match args.get("indent") {
+        Some(Value::Number(indent)) => Some(
+            indent
+                .as_u64()
+                .ok_or("to_json: Error indent is not unsigned integer")? as usize,
+        ),
+        None => None,
+        _ => return Err("to_json: Invalid type for arg indent, expected number".into()),
+    };
+    if !flag_arg("to_json", args, "pretty", indent.is_some())? {
+        return Ok(Value::String(piped_arg.to_string()));
+    }
+    let indent = " ".repeat(indent.unwrap_or(2));
+    let mut json = vec![];
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+    let mut serializer = serde_json::Serializer::with_formatter(&mut json, formatter);
+    piped_arg
+        .serialize(&mut serializer)
+        .map_err(|e| format!("to_json: error serializing json : {}", e))?;
+    return String::from_utf8(json)
+        .map(Value::String)
+        .map_err(|e| format!("to_json: utf8 decoding error : {}", e).into());
+}
+
+pub fn to_yaml(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    return serialize("to_yaml", "yaml", piped_arg);
+}
+
+pub fn to_toml(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    return serialize("to_toml", "toml", piped_arg);
+}
+
+pub fn to_hcl(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    return serialize("to_hcl", "hcl", piped_arg);
+}
+
+pub fn to_env(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    return serialize("to_env", "env", piped_arg);
+}
+
+fn parse(name: &str, format: &str, piped_arg: &Value) -> Result<Value> {
+    if let Value::String(data) = piped_arg {
+        return formats::parse(format, data).map_err(|e| format!("{}: {:#}", name, e).into());
+    } else {
+        return Err(format!("{}: Invalid type, expected string", name).into());
+    }
+}
+
+pub fn from_yaml(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    return parse("from_yaml", "yaml", piped_arg);
+}
+
+pub fn from_toml(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    return parse("from_toml", "toml", piped_arg);
+}
+
+pub fn from_hcl(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    return parse("from_hcl", "hcl", piped_arg);
+}
+
+pub fn from_ini(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    return parse("from_ini", "ini", piped_arg);
+}
+
+pub fn from_csv(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    let data = if let Value::String(data) = piped_arg {
+        data
+    } else {
+        return Err("from_csv: Invalid type, expected string".into());
+    };
+    let delimiter = match args.get("delimiter") {
+        Some(Value::String(delimiter)) if delimiter.len() == 1 => delimiter.as_bytes()[0],
+        None => b',',
+        _ => return Err("from_csv: Invalid type for arg delimiter, expected single character string".into()),
+    };
+    let headers = flag_arg("from_csv", args, "headers", true)?;
+    return formats::parse_csv(data, headers, delimiter).map_err(|e| format!("from_csv: {:#}", e).into());
+}
+
+/// width is a number of spaces or a string used as prefix,
+/// prefix is accepted too since it is the arg of the tera builtin indent that these filters replace
+fn indent_prefix(name: &str, args: &HashMap<String, Value>, default: usize) -> Result<String> {
+    match (args.get("width"), args.get("prefix")) {
+        (Some(_), Some(_)) => Err(format!("{}: expected only one of the args width or prefix", name).into()),
+        (Some(Value::Number(width)), None) => Ok(" ".repeat(
+            width
+                .as_u64()
+                .ok_or(format!("{}: Error width is not unsigned integer", name))? as usize,
+        )),
+        (Some(Value::String(prefix)), None) | (None, Some(Value::String(prefix))) => Ok(prefix.clone()),
+        (None, None) => Ok(" ".repeat(default)),
+        (None, Some(_)) => Err(format!("{}: Invalid type for arg prefix, expected string", name).into()),
+        _ => Err(format!("{}: Invalid type for arg width, expected number or string", name).into()),
+    }
+}
+
+pub fn indent(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::String(text) = piped_arg {
+        let prefix = indent_prefix("indent", args, 4)?;
+        let first = flag_arg("indent", args, "first", false)?;
+        let blank = flag_arg("indent", args, "blank", false)?;
+        return Ok(Value::String(indent_lines(text, &prefix, first, blank)));
+    } else {
+        return Err("indent: Invalid type, expected string".into());
+    }
+}
+
+pub fn nindent(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::String(text) = piped_arg {
+        let prefix = indent_prefix("nindent", args, 4)?;
+        let blank = flag_arg("nindent", args, "blank", false)?;
+        return Ok(Value::String("\n".to_owned() + &indent_lines(text, &prefix, true, blank)));
+    } else {
+        return Err("nindent: Invalid type, expected string".into());
+    }
+}
+
+pub fn dedent(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::String(text) = piped_arg {
+        let margin = text
+            .split('\n')
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| &line[..line.len() - line.trim_start().len()])
+            .fold(None, |margin: Option<&str>, whitespace| match margin {
+                None => Some(whitespace),
+                Some(margin) => {
+                    let common = margin
+                        .chars()
+                        .zip(whitespace.chars())
+                        .take_while(|(a, b)| a == b)
+                        .map(|(a, _)| a.len_utf8())
+                        .sum();
+                    Some(&margin[..common])
+                }
+            })
+            .unwrap_or("");
+        let lines: Vec<_> = text
+            .split('\n')
+            .map(|line| line.strip_prefix(margin).unwrap_or_else(|| line.trim_start()))
+            .collect();
+        return Ok(Value::String(lines.join("\n")));
+    } else {
+        return Err("dedent: Invalid type, expected string".into());
+    }
+}
+
+pub fn wrap(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    let text = if let Value::String(text) = piped_arg {
+        text
+    } else {
+        return Err("wrap: Invalid type, expected string".into());
+    };
+    let width = match args.get("width") {
+        Some(Value::Number(width)) => width.as_u64().ok_or("wrap: Error width is not unsigned integer")? as usize,
+        None => 79,
+        _ => return Err("wrap: Invalid type for arg width, expected number".into()),
+    };
+    let mut lines = vec![];
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+                lines.push(line);
+                line = String::new();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        lines.push(line);
+    }
+    return Ok(Value::String(lines.join("\n")));
+}
@@ -0,0 +1,182 @@
+use anyhow::{anyhow, Context, Result};
+use ini::Ini;
+use molysite::hcl::parse_hcl;
+use serde_json::{Map, Value};
+use std::ffi::OsStr;
+use std::path::Path;
+
+pub const OUTPUT_FORMATS: &[&str] = &["json", "json-pretty", "yaml", "toml", "hcl", "env"];
+
+pub const INPUT_FORMATS: &[&str] = &["yaml", "yml", "json", "toml", "tml", "hcl", "tfvars", "tf", "ini", "csv"];
+
+pub fn format_from_extension(path: &str) -> Result<String> {
+    Path::new(path)
+        .extension()
+        .and_then(OsStr::to_str)
+        .map(str::to_string)
+        .ok_or(anyhow!("Error no extension found in file {}", path))
+}
+
+pub fn parse(format: &str, data: &str) -> Result<Value> {
+    match format {
+        "yaml" | "yml" => serde_yaml::from_str(data).context("Error parsing yaml"),
+        "json" => data.parse::<Value>().context("Error parsing json"),
+        "toml" | "tml" => {
+            let value = data.parse::<toml::Value>().context("Error parsing toml")?;
+            serde_json::to_value(value).context("Error converting toml to json")
+        }
+        "hcl" | "tfvars" | "tf" => {
+            let value = parse_hcl(data).map_err(|e| anyhow!("Error {} parsing hcl/tf/tfvars", e))?;
+            value
+                .to_string()
+                .parse::<Value>()
+                .context("Error parsing json of hcl/tf/tfvars")
+        }
+        "ini" => parse_ini(data),
+        "csv" => parse_csv(data, true, b','),
+        _ => Err(anyhow!("Format {} not recognized, expected one of {}", format, INPUT_FORMATS.join(","))),
+    }
+}
+
+/// keys outside of any section are in the root object, each section is an object
+pub fn parse_ini(data: &str) -> Result<Value> {
+    let ini = Ini::load_from_str(data).context("Error parsing ini")?;
+    let mut root = Map::new();
+    for (section, properties) in ini.iter() {
+        let mut object = Map::new();
+        for (k, v) in properties.iter() {
+            object.insert(k.to_string(), Value::String(v.to_string()));
+        }
+        match section {
+            Some(section) => {
+                root.insert(section.to_string(), Value::Object(object));
+            }
+            None => root.extend(object),
+        }
+    }
+    Ok(Value::Object(root))
+}
+
+/// with headers each row is an object keyed by the header, without headers each row is a list
+pub fn parse_csv(data: &str, headers: bool, delimiter: u8) -> Result<Value> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(headers)
+        .delimiter(delimiter)
+        .from_reader(data.as_bytes());
+    let names = if headers {
+        reader.headers().context("Error parsing csv headers")?.clone()
+    } else {
+        csv::StringRecord::new()
+    };
+    let mut rows = vec![];
+    for record in reader.records() {
+        let record = record.context("Error parsing csv")?;
+        let fields = record.iter().map(|field| Value::String(field.to_string()));
+        if headers {
+            rows.push(Value::Object(names.iter().map(str::to_string).zip(fields).collect()));
+        } else {
+            rows.push(Value::Array(fields.collect()));
+        }
+    }
+    Ok(Value::Array(rows))
+}
+
+pub fn serialize(value: &Value, format: &str) -> Result<String> {
+    match format {
+        "json" => Ok(value.to_string()),
+        "json-pretty" => serde_json::to_string_pretty(value).context("Error serializing to json"),
+        "yaml" | "yml" => {
+            let yaml = serde_yaml::to_string(value).context("Error serializing to yaml")?;
+            Ok(yaml.trim_start_matches("---\n").to_string())
+        }
+        "toml" | "tml" => {
+            let value = toml::Value::try_from(value)
+                .context("Error converting to toml, null values are not supported by toml")?;
+            toml::to_string(&value).context("Error serializing to toml")
+        }
+        "hcl" | "tfvars" | "tf" => to_hcl(value),
+        "env" => to_env(value),
+        _ => Err(anyhow!("Format {} not recognized, expected one of {}", format, OUTPUT_FORMATS.join(","))),
+    }
+}
+
+fn to_hcl(value: &Value) -> Result<String> {
+    let object = value.as_object().context("Error expected object in root to serialize as hcl")?;
+    let mut out = String::new();
+    for (k, v) in object.iter() {
+        out.push_str(&format!("{} = {}\n", hcl_key(k), hcl_value(v, 0)));
+    }
+    Ok(out)
+}
+
+fn hcl_key(key: &str) -> String {
+    let is_identifier = key
+        .chars()
+        .next()
+        .map(|c| c.is_ascii_alphabetic() || c == '_')
+        .unwrap_or(false)
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if is_identifier {
+        key.to_string()
+    } else {
+        Value::String(key.to_string()).to_string()
+    }
+}
+
+fn hcl_value(value: &Value, level: usize) -> String {
+    match value {
+        Value::Array(items) => {
+            let items: Vec<_> = items.iter().map(|item| hcl_value(item, level)).collect();
+            format!("[{}]", items.join(", "))
+        }
+        Value::Object(object) => {
+            if object.is_empty() {
+                return "{}".to_string();
+            }
+            let spaces = "  ".repeat(level + 1);
+            let mut out = "{\n".to_string();
+            for (k, v) in object.iter() {
+                out.push_str(&format!("{}{} = {}\n", spaces, hcl_key(k), hcl_value(v, level + 1)));
+            }
+            out.push_str(&format!("{}}}", "  ".repeat(level)));
+            out
+        }
+        _ => value.to_string(),
+    }
+}
+
+fn to_env(value: &Value) -> Result<String> {
+    let object = value.as_object().context("Error expected object in root to serialize as env")?;
+    let mut out = String::new();
+    for (k, v) in object.iter() {
+        let v = match v {
+            Value::String(s) => s.clone(),
+            Value::Null => String::new(),
+            _ => v.to_string(),
+        };
+        out.push_str(&format!("{}={}\n", k, env_quote(&v)));
+    }
+    Ok(out)
+}
+
+fn env_quote(value: &str) -> String {
+    let needs_quotes = value
+        .chars()
+        .any(|c| c.is_whitespace() || "\"'`$\\#;&|<>(){}*?!".contains(c));
+    if !needs_quotes {
+        return value.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' | '\\' | '$' | '`' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
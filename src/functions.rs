@@ -1,5 +1,12 @@
-use crate::inners::exec_cmd;
+use crate::formats;
+use crate::inners::{exec_argv, exec_cmd, indent_lines};
+use crate::output;
+use crate::query::Query;
+use crate::sandbox;
+use glob::glob;
 use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
 use std::process::Command;
 use tera::{Error, Result, Value};
 use anyhow::Context;
@@ -14,45 +21,32 @@ pub fn bash(args: &HashMap<String, Value>) -> Result<Value> {
     let mut bash_cmd = Command::new("bash");
     bash_cmd.arg("-c").arg(command);
 
-    return exec_cmd(&mut bash_cmd, command, &args);
+    return exec_cmd("bash", &mut bash_cmd, command, &args);
 }
 
-pub fn tab_all_lines(args: &HashMap<String, Value>) -> Result<Value> {
+fn tab_lines(name: &str, args: &HashMap<String, Value>, first: bool) -> Result<Value> {
     if let Some(Value::String(lines)) = args.get("lines") {
         if let Some(Value::Number(num_spaces)) = args.get("num_spaces") {
-            let num_spaces = num_spaces.as_u64().ok_or(Error::from(
-                "tab_all_lines: Error number of spaces is not unsigned integer",
-            ))? as usize;
+            let num_spaces = num_spaces.as_u64().ok_or(Error::from(format!(
+                "{}: Error number of spaces is not unsigned integer",
+                name
+            )))? as usize;
             let spaces = " ".repeat(num_spaces);
-            let lines: Vec<_> = lines.split('\n').map(|line| spaces.clone() + line).collect();
-            return Ok(Value::String(lines.join("\n")));
+            return Ok(Value::String(indent_lines(lines, &spaces, first, true)));
         } else {
-            return Err("tab_all_lines: Invalid type for arg num_spaces, expected number".into());
+            return Err(format!("{}: Invalid type for arg num_spaces, expected number", name).into());
         }
     } else {
-        return Err("tab_all_lines: Invalid type for arg lines, expected string".into());
+        return Err(format!("{}: Invalid type for arg lines, expected string", name).into());
     }
 }
 
+pub fn tab_all_lines(args: &HashMap<String, Value>) -> Result<Value> {
+    return tab_lines("tab_all_lines", args, true);
+}
+
 pub fn tab_all_lines_except_first(args: &HashMap<String, Value>) -> Result<Value> {
-    if let Some(Value::String(lines)) = args.get("lines") {
-        if let Some(Value::Number(num_spaces)) = args.get("num_spaces") {
-            let num_spaces = num_spaces.as_u64().ok_or(Error::from(
-                "tab_all_lines: Error number of spaces is not unsigned integer",
-            ))? as usize;
-            let spaces = " ".repeat(num_spaces);
-            let lines: Vec<_> = lines
-                .split('\n')
-                .enumerate()
-                .map(|(idx, line)| if idx == 0 { line.to_owned() } else { spaces.clone() + line })
-                .collect();
-            return Ok(Value::String(lines.join("\n")));
-        } else {
-            return Err("tab_all_lines: Invalid type for arg num_spaces, expected number".into());
-        }
-    } else {
-        return Err("tab_all_lines: Invalid type for arg lines, expected string".into());
-    }
+    return tab_lines("tab_all_lines_except_first", args, false);
 }
 
 pub fn str(args: &HashMap<String, Value>) -> Result<Value> {
@@ -72,3 +66,79 @@ pub fn from_json(args: &HashMap<String, Value>) -> Result<Value> {
         .map_err(|e| e.to_string())?;
     return Ok(value);
 }
+
+pub fn query(args: &HashMap<String, Value>) -> Result<Value> {
+    let value = args.get("value").ok_or("query: expected value argument".to_owned())?;
+    let expr = if let Some(Value::String(expr)) = args.get("expr") {
+        expr
+    } else {
+        return Err("query: Invalid type for arg expr, expected string".into());
+    };
+    let query = Query::parse(expr).map_err(|e| format!("query: {}", e))?;
+    return query.run_to_value(value).map_err(|e| format!("query: {}", e).into());
+}
+
+pub fn output_file(args: &HashMap<String, Value>) -> Result<Value> {
+    if let Some(Value::String(path)) = args.get("path") {
+        return Ok(Value::String(output::output_file_marker(path)));
+    } else {
+        return Err("output_file: Invalid type for arg path, expected string".into());
+    }
+}
+
+pub fn exec(args: &HashMap<String, Value>) -> Result<Value> {
+    let stdin = match args.get("stdin") {
+        Some(Value::String(stdin)) => Some(stdin.as_str()),
+        None => None,
+        _ => return Err("exec: Invalid type for arg stdin, expected string".into()),
+    };
+    return exec_argv("exec", args, stdin);
+}
+
+fn load_file(name: &str, path: &str, format: Option<&str>) -> Result<Value> {
+    let path = sandbox::check_path(name, path)?;
+    let path = path.to_str().ok_or(format!("{}: error decoding path", name))?;
+    let format = match format {
+        Some(format) if format != "auto" => format.to_string(),
+        _ => formats::format_from_extension(path).map_err(|e| format!("{}: {}", name, e))?,
+    };
+    let data = fs::read_to_string(path).map_err(|e| format!("{}: error reading file {} : {:?}", name, path, e))?;
+    return formats::parse(&format, &data).map_err(|e| format!("{}: error loading {} : {:#}", name, path, e).into());
+}
+
+fn format_arg<'a>(name: &str, args: &'a HashMap<String, Value>) -> Result<Option<&'a str>> {
+    match args.get("format") {
+        Some(Value::String(format)) => Ok(Some(format)),
+        None => Ok(None),
+        _ => Err(format!("{}: Invalid type for arg format, expected string", name).into()),
+    }
+}
+
+pub fn load(args: &HashMap<String, Value>) -> Result<Value> {
+    if let Some(Value::String(path)) = args.get("path") {
+        return load_file("load", path, format_arg("load", args)?);
+    } else {
+        return Err("load: Invalid type for arg path, expected string".into());
+    }
+}
+
+pub fn load_glob(args: &HashMap<String, Value>) -> Result<Value> {
+    let pattern = if let Some(Value::String(pattern)) = args.get("pattern") {
+        pattern
+    } else {
+        return Err("load_glob: Invalid type for arg pattern, expected string".into());
+    };
+    let format = format_arg("load_glob", args)?;
+    let paths = glob(pattern).map_err(|e| format!("load_glob: error in glob : {:?}", e))?;
+    let mut files = serde_json::Map::new();
+    for entry in paths {
+        let path = entry.map_err(|e| format!("load_glob: error in glob : {:?}", e))?;
+        let path = format!("{}", path.display());
+        if !sandbox::is_allowed(&path) || !Path::new(&path).is_file() {
+            continue;
+        }
+        let value = load_file("load_glob", &path, format)?;
+        files.insert(path, value);
+    }
+    return Ok(Value::Object(files));
+}
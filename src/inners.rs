@@ -1,31 +1,278 @@
+use crate::cache;
 use std::collections::HashMap;
-use std::process::Command;
+use std::env;
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 use tera::{Result, Value};
 use anyhow::Context;
 
-pub fn exec_cmd(command: &mut Command, cmd_str: &str, env: &HashMap<String, Value>) -> Result<Value> {
-    for (k, v) in env.iter() {
-        let value = if let Value::String(data) = v {
-            data
-        } else {
-            return Err("Invalid type for args, expected string".into());
+/// args that configure how commands are executed, they are not exported as env vars
+pub const EXEC_OPTIONS: &[&str] = &["check", "timeout", "capture_stderr", "structured", "cache"];
+
+pub struct ExecOptions {
+    pub check: bool,
+    pub timeout: Option<Duration>,
+    pub capture_stderr: bool,
+    pub structured: bool,
+    pub cache: bool,
+}
+
+impl ExecOptions {
+    pub fn from_args(args: &HashMap<String, Value>) -> Result<ExecOptions> {
+        let flag = |name: &str, default: bool| match args.get(name) {
+            Some(Value::Bool(value)) => Ok(*value),
+            None => Ok(default),
+            _ => Err(format!("Invalid type for arg {}, expected boolean", name)),
         };
-        command.env(k, value);
+        let timeout = match args.get("timeout") {
+            Some(Value::Number(seconds)) => {
+                let seconds = seconds
+                    .as_f64()
+                    .filter(|seconds| *seconds >= 0.0)
+                    .ok_or("Invalid arg timeout, expected positive number of seconds")?;
+                Some(Duration::from_secs_f64(seconds))
+            }
+            None => None,
+            _ => return Err("Invalid type for arg timeout, expected number of seconds".into()),
+        };
+        return Ok(ExecOptions {
+            check: flag("check", true)?,
+            timeout,
+            capture_stderr: flag("capture_stderr", false)?,
+            structured: flag("structured", false)?,
+            cache: flag("cache", true)?,
+        });
+    }
+}
+
+/// args of the filters and functions that are not exported as env vars
+pub const RESERVED_ARGS: &[&str] = &["command", "expression", "cmd", "stdin", "env"];
+
+/// strings are exported as is, null as empty and other values as json
+fn env_value(value: &Value) -> String {
+    match value {
+        Value::String(data) => data.clone(),
+        Value::Null => String::new(),
+        _ => value.to_string(),
+    }
+}
+
+/// exports the entries of the env arg as env vars of the command
+fn set_env(command: &mut Command, args: &HashMap<String, Value>) -> Result<()> {
+    match args.get("env") {
+        Some(Value::Object(env)) => {
+            for (k, v) in env.iter() {
+                command.env(k, env_value(v));
+            }
+        }
+        Some(Value::Null) | None => {}
+        _ => return Err("Invalid type for arg env, expected object".into()),
+    }
+    return Ok(());
+}
+
+/// runs the command exporting every non reserved arg as an env var, name prefixes the errors of the args
+pub fn exec_cmd(name: &str, command: &mut Command, cmd_str: &str, args: &HashMap<String, Value>) -> Result<Value> {
+    let options = ExecOptions::from_args(args).map_err(|e| format!("{}: {}", name, e))?;
+    for (k, v) in args.iter() {
+        if EXEC_OPTIONS.contains(&k.as_str()) || RESERVED_ARGS.contains(&k.as_str()) {
+            continue;
+        }
+        command.env(k, env_value(v));
+    }
+    set_env(command, args).map_err(|e| format!("{}: {}", name, e))?;
+    return run_cmd(command, cmd_str, None, &options);
+}
+
+fn read_in_thread<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut data = vec![];
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut data);
+        }
+        data
+    })
+}
+
+fn wait(child: &mut Child, cmd_str: &str, timeout: Option<Duration>) -> Result<ExitStatus> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => {
+            return child
+                .wait()
+                .context(format!("Error executing command : {}", cmd_str))
+                .map_err(|e| e.to_string().into())
+        }
+    };
+    let start = Instant::now();
+    loop {
+        let status = child
+            .try_wait()
+            .context(format!("Error executing command : {}", cmd_str))
+            .map_err(|e| e.to_string())?;
+        if let Some(status) = status {
+            return Ok(status);
+        }
+        if start.elapsed() >= timeout {
+            // the command runs in its own process group, kill the processes it started too
+            unsafe {
+                libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+            }
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("command {} timed out after {:?}", cmd_str, timeout).into());
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// runs the command or returns its cached result if the same command with the same env and stdin already ran
+/// in the same dir, the env vars inherited from j2_render are not part of the key
+pub fn run_cmd(command: &mut Command, cmd_str: &str, stdin: Option<&str>, options: &ExecOptions) -> Result<Value> {
+    if !options.cache {
+        return spawn_cmd(command, cmd_str, stdin, options);
+    }
+    let current_dir = match command.get_current_dir() {
+        Some(dir) => dir.to_path_buf(),
+        None => env::current_dir().map_err(|e| format!("Error getting current dir : {}", e))?,
+    };
+    let key = cache::key(&format!(
+        "{:?} {:?} {:?} {:?} {:?} check={} capture_stderr={} structured={}",
+        command.get_program(),
+        command.get_args().collect::<Vec<_>>(),
+        command.get_envs().collect::<Vec<_>>(),
+        current_dir,
+        stdin,
+        options.check,
+        options.capture_stderr,
+        options.structured,
+    ));
+    if let Some(value) = cache::get(&key) {
+        return Ok(value);
+    }
+    let value = spawn_cmd(command, cmd_str, stdin, options)?;
+    cache::set(&key, &value);
+    return Ok(value);
+}
+
+fn spawn_cmd(command: &mut Command, cmd_str: &str, stdin: Option<&str>, options: &ExecOptions) -> Result<Value> {
+    if options.timeout.is_some() {
+        command.process_group(0);
     }
-    let out = command
-        .output()
+    let mut child = command
+        .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
         .context(format!("Error executing command : {}", cmd_str))
         .map_err(|e| e.to_string())?;
 
-    let stdout = String::from_utf8(out.stdout)
+    // written from another thread so a command that doesn't read its stdin can't block us
+    let writer = match (stdin, child.stdin.take()) {
+        (Some(data), Some(mut child_stdin)) => {
+            let data = data.to_owned();
+            Some(thread::spawn(move || child_stdin.write_all(data.as_bytes())))
+        }
+        _ => None,
+    };
+    let stdout_reader = read_in_thread(child.stdout.take());
+    let stderr_reader = read_in_thread(child.stderr.take());
+
+    let status = wait(&mut child, cmd_str, options.timeout)?;
+    if let Some(writer) = writer {
+        // a command may exit without reading all its stdin, that is not an error
+        let _ = writer.join();
+    }
+    let stdout = stdout_reader.join().map_err(|_| format!("Error reading stdout of command {}", cmd_str))?;
+    let stderr = stderr_reader.join().map_err(|_| format!("Error reading stderr of command {}", cmd_str))?;
+
+    let stdout = String::from_utf8(stdout)
         .context(format!("bash: Error reading stdout of command {}", cmd_str))
         .map_err(|e| e.to_string())?;
-    let stderr = String::from_utf8(out.stderr)
+    let stderr = String::from_utf8(stderr)
         .context(format!("bash: Error reading stderr of command {}", cmd_str))
         .map_err(|e| e.to_string())?;
+
+    if options.check && !status.success() {
+        let code = status.code().map(|code| code.to_string()).unwrap_or("signal".to_owned());
+        return Err(format!("command {} exited with {}, stderr : {}", cmd_str, code, stderr).into());
+    }
+
+    if options.structured {
+        let mut result = serde_json::Map::new();
+        result.insert("stdout".to_owned(), Value::String(stdout));
+        result.insert("stderr".to_owned(), Value::String(stderr));
+        result.insert("code".to_owned(), status.code().map(Value::from).unwrap_or(Value::Null));
+        return Ok(Value::Object(result));
+    }
+    if options.capture_stderr {
+        return Ok(Value::String(stdout + &stderr));
+    }
     if stderr != "" {
         eprintln!("command {} stderr : {}", cmd_str, stderr);
     }
 
     return Ok(Value::String(stdout));
 }
+
+/// runs cmd, a list of program and arguments, without a shell
+pub fn exec_argv(name: &str, args: &HashMap<String, Value>, stdin: Option<&str>) -> Result<Value> {
+    let argv = if let Some(Value::Array(argv)) = args.get("cmd") {
+        argv
+    } else {
+        return Err(format!("{}: Invalid type for arg cmd, expected list of strings", name).into());
+    };
+    let argv = argv
+        .iter()
+        .map(|arg| match arg {
+            Value::String(arg) => Ok(arg.clone()),
+            Value::Number(_) | Value::Bool(_) => Ok(arg.to_string()),
+            _ => Err(format!("{}: Invalid type for arg cmd, expected list of strings", name)),
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let (program, program_args) = argv
+        .split_first()
+        .ok_or(format!("{}: Invalid arg cmd, expected at least the program", name))?;
+    let options = ExecOptions::from_args(args).map_err(|e| format!("{}: {}", name, e))?;
+
+    let mut command = Command::new(program);
+    command.args(program_args);
+    set_env(&mut command, args).map_err(|e| format!("{}: {}", name, e))?;
+    return run_cmd(&mut command, &argv.join(" "), stdin, &options);
+}
+
+/// prefixes the lines of text, the first line only if first and blank lines only if blank
+pub fn indent_lines(text: &str, prefix: &str, first: bool, blank: bool) -> String {
+    let lines: Vec<_> = text
+        .split('\n')
+        .enumerate()
+        .map(|(idx, line)| {
+            if (idx == 0 && !first) || (line.trim().is_empty() && !blank) {
+                line.to_owned()
+            } else {
+                prefix.to_owned() + line
+            }
+        })
+        .collect();
+    return lines.join("\n");
+}
+
+/// merges other into base recursively, values of other replace the ones of base except objects that are merged
+pub fn deep_merge(base: &mut Value, other: &Value) {
+    match (base, other) {
+        (Value::Object(base), Value::Object(other)) => {
+            for (k, v) in other.iter() {
+                match base.get_mut(k) {
+                    Some(existing) => deep_merge(existing, v),
+                    None => {
+                        base.insert(k.clone(), v.clone());
+                    }
+                }
+            }
+        }
+        (base, other) => *base = other.clone(),
+    }
+}
@@ -1,28 +1,53 @@
-use molysite::hcl::parse_hcl;
-use serde_json;
-use serde_yaml;
-use std::ffi::OsStr;
 use std::io::Write;
 use std::process::exit;
+use std::thread;
+use std::time::{Duration, SystemTime};
 use std::{
     collections::HashMap,
     env, fs,
     io::{self, Read},
     path::Path,
 };
+use query::Query;
 use tera::{Context, Tera};
 use anyhow::{Result, Context as AnyhowContext, anyhow};
 
+mod ansible;
+mod cache;
+mod files;
 mod filters;
+mod formats;
 mod functions;
 mod inners;
+mod output;
+mod paths;
+mod query;
+mod required;
+mod sandbox;
+mod sprig;
 mod testers;
 
+const UNCHANGED_EXIT_CODE: i32 = 2;
+
 pub struct Config {
     pub template: String,
+    pub template_name: String,
     pub context: Context,
     pub out_file: Option<String>,
     pub print_ctx: bool,
+    pub print_ctx_format: String,
+    pub query: Option<String>,
+    pub watch: bool,
+    pub out_dir: Option<String>,
+    pub foreach: Option<String>,
+    pub strict: bool,
+    pub sandbox: bool,
+    pub allowed: sandbox::Capabilities,
+    pub root: Option<String>,
+    pub cache_dir: Option<String>,
+    pub cache_ttl: u64,
+    pub write_options: output::WriteOptions,
+    pub watched_files: Vec<String>,
 }
 
 pub fn help() {
@@ -40,14 +65,34 @@ j2_render [FLAGS]
 
     --stdin/-i FORMAT   -- read from stdin context or template
     --out/-o file_path   -- output file for rendered template, default stdout
+    --out-dir/-d dir_path   -- directory for the files created with output_file(path=...) in the template
+    --foreach key   -- renders the template once per item of the list at key in the context,
+                       the item is available as `item` and --out is rendered as a template to get each file path
     --env/-e    -- load env vars in ctx
     --file/-f FILE_PATH   -- loads a file as context or template depending on extension or format
     --var/-v VAR   -- adds a pair key value to the context or a template depending on format
     --print-ctx/-p   -- print the context as json and exits
+    --print-ctx-format OUT_FORMAT   -- print the context as OUT_FORMAT and exits
+    --query/-q QUERY   -- print the results of a jq like QUERY over the context and exits
+    --watch/-w   -- keeps running and renders again when any --file or --template changes
+    --check/-c   -- prints a diff and fails if the output files differ from the rendered template, does not write them
+    --mode MODE   -- octal permissions of the --out file, like 0600
+    --preserve   -- fails if the owner of the existing --out file can't be kept, its mode is always kept
+    --backup   -- copies the existing --out file to --out.bak before replacing it
+    --only-if-changed   -- does not write output files if their content is the same, exits with 2 if all unchanged
+    --strict   -- renders every required(...) value that is missing and reports them all instead of the first one
+    --sandbox   -- disables templates access to commands, files and env vars
+    --allow CAPABILITIES   -- enables only the CAPABILITIES in --sandbox, implies --sandbox
+    --root dir_path   -- restricts templates file access to files inside dir_path
+    --cache-dir dir_path   -- caches the output of commands run by templates in dir_path between renders
+    --cache-ttl seconds   -- seconds a command output is cached in --cache-dir, default 3600
     --help/-h   -- shows this help
 
     VAR: key[+FORMAT]=value
-    FORMAT: yaml yml json toml tml hcl tfvars tf
+    FORMAT: yaml yml json toml tml hcl tfvars tf ini
+    OUT_FORMAT: json json-pretty yaml toml hcl env
+    CAPABILITIES: comma separated list of exec fs-read env
+    QUERY: .key.other[0] .list[] .\"key with spaces\" .list[] | .name
     "
     )
 }
@@ -68,10 +113,25 @@ pub fn parse_args() -> Result<Config> {
 
     let mut config = Config{
         template: String::new(),
+        template_name: "template".to_string(),
         context: Context::new(),
         out_file: None,
-        print_ctx: false
+        print_ctx: false,
+        print_ctx_format: "json".to_string(),
+        query: None,
+        watch: false,
+        out_dir: None,
+        foreach: None,
+        strict: false,
+        sandbox: false,
+        allowed: sandbox::Capabilities::none(),
+        root: None,
+        cache_dir: None,
+        cache_ttl: cache::DEFAULT_TTL,
+        write_options: output::WriteOptions::default(),
+        watched_files: vec![],
     };
+    let mut read_stdin = false;
 
     args.pop(); // binary name
 
@@ -86,12 +146,77 @@ pub fn parse_args() -> Result<Config> {
                 let value = parts.pop().ok_or(anyhow!("Error no key=value found"))?;
 
                 if let Some((format, _)) = extract_format(key) {
-                    process_inputs(&mut config, format, value.to_string()).context("Error processing inputs from --var arg")?;
+                    process_inputs(&mut config, format, "--var", value.to_string()).context("Error processing inputs from --var arg")?;
                 } else {
                     config.context.insert(key, &value)
                 }
             }
             "--print-ctx" | "-p" => config.print_ctx = true,
+            "--print-ctx-format" => {
+                let format = args
+                    .pop()
+                    .ok_or(anyhow!("error specified --print-ctx-format flag but not format provided"))?;
+                config.print_ctx = true;
+                config.print_ctx_format = format;
+            }
+            "--query" | "-q" => {
+                let query = args
+                    .pop()
+                    .ok_or(anyhow!("error specified --query/-q flag but not query provided"))?;
+                config.query = Some(query);
+            }
+            "--watch" | "-w" => config.watch = true,
+            "--check" | "-c" => config.write_options.check = true,
+            "--mode" => {
+                let mode = args
+                    .pop()
+                    .ok_or(anyhow!("error specified --mode flag but not mode provided"))?;
+                config.write_options.mode = Some(output::parse_mode(&mode)?);
+            }
+            "--preserve" => config.write_options.preserve = true,
+            "--backup" => config.write_options.backup = true,
+            "--only-if-changed" => config.write_options.only_if_changed = true,
+            "--out-dir" | "-d" => {
+                let dirpath = args
+                    .pop()
+                    .ok_or(anyhow!("error specified --out-dir/-d flag but not directory path provided"))?;
+                config.out_dir = Some(dirpath);
+            }
+            "--foreach" => {
+                let key = args
+                    .pop()
+                    .ok_or(anyhow!("error specified --foreach flag but not context key provided"))?;
+                config.foreach = Some(key);
+            }
+            "--strict" => config.strict = true,
+            "--sandbox" => config.sandbox = true,
+            "--allow" => {
+                let list = args
+                    .pop()
+                    .ok_or(anyhow!("error specified --allow flag but not capabilities provided"))?;
+                config.sandbox = true;
+                config.allowed.allow(&list)?;
+            }
+            "--root" => {
+                let root = args
+                    .pop()
+                    .ok_or(anyhow!("error specified --root flag but not directory provided"))?;
+                config.root = Some(root);
+            }
+            "--cache-dir" => {
+                let dir = args
+                    .pop()
+                    .ok_or(anyhow!("error specified --cache-dir flag but not directory provided"))?;
+                config.cache_dir = Some(dir);
+            }
+            "--cache-ttl" => {
+                let ttl = args
+                    .pop()
+                    .ok_or(anyhow!("error specified --cache-ttl flag but not seconds provided"))?;
+                config.cache_ttl = ttl
+                    .parse()
+                    .with_context(|| format!("Error parsing --cache-ttl {}, expected seconds", ttl))?;
+            }
             "--out" | "-o" => {
                 let filepath = args
                     .pop()
@@ -104,7 +229,8 @@ pub fn parse_args() -> Result<Config> {
                     .ok_or(anyhow!("error specified --stdin/-i flag but not format provided"))?;
                 let mut data = String::new();
                 io::stdin().read_to_string(&mut data).context("Error readinf from stdin")?;
-                process_inputs(&mut config, format, data).context("Error parsing inputs from --stdin")?;
+                read_stdin = true;
+                process_inputs(&mut config, format, "stdin", data).context("Error parsing inputs from --stdin")?;
             }
             "--file" | "-f" => {
                 let path = args
@@ -113,22 +239,20 @@ pub fn parse_args() -> Result<Config> {
                 let (format, path) = if let Some((format, path)) = extract_format(&path) {
                     (format, path)
                 } else {
-                    let extension = Path::new(&path)
-                        .extension()
-                        .and_then(OsStr::to_str)
-                        .ok_or(anyhow!("Error no extension found in ctx file"))?;
-                    (extension.to_string(), path)
+                    (formats::format_from_extension(&path)?, path)
                 };
 
+                config.watched_files.push(path.clone());
                 let data = fs::read_to_string(&path).with_context(|| format!("Error reading context file {}", path))?;
-                process_inputs(&mut config, format, data).with_context(|| format!("Error parsing inputs from --file {}", path))?;
+                process_inputs(&mut config, format, &path, data).with_context(|| format!("Error parsing inputs from --file {}", path))?;
             }
             "--template" | "-t" => {
                 let path = args
                     .pop()
                     .ok_or(anyhow!("error specified --template/-t flag but not context file path provided"))?;
+                config.watched_files.push(path.clone());
                 let data = fs::read_to_string(&path).with_context(|| format!("Error reading template file {}", path))?;
-                process_inputs(&mut config, "tpl".into(), data).with_context(|| format!("Error parsing inputs from --file {}", path))?;
+                process_inputs(&mut config, "tpl".into(), &path, data).with_context(|| format!("Error parsing inputs from --file {}", path))?;
             }
             "--env" | "-e" => {
                 let env_vars = env::vars().collect::<HashMap<String, String>>();
@@ -143,12 +267,26 @@ pub fn parse_args() -> Result<Config> {
             _ => panic!("Error argument {} not recognized", arg),
         }
     }
+    if config.foreach.is_some() && config.out_file.is_none() {
+        return Err(anyhow!("error --foreach requires --out/-o path template"));
+    }
+    let has_out = config.out_file.is_some() || config.out_dir.is_some();
+    if config.write_options.check && !has_out {
+        return Err(anyhow!("error --check/-c requires --out/-o or --out-dir/-d to compare with"));
+    }
+    if config.write_options.only_if_changed && !has_out {
+        return Err(anyhow!("error --only-if-changed requires --out/-o or --out-dir/-d to compare with"));
+    }
+    if config.watch && read_stdin {
+        return Err(anyhow!("error --watch/-w can not be used with --stdin/-i, stdin can only be read once"));
+    }
     return Ok(config);
 }
 
-pub fn process_inputs(mut config: &mut Config, format: String, data: String) -> Result<()> {
+pub fn process_inputs(mut config: &mut Config, format: String, name: &str, data: String) -> Result<()> {
     if format == "template" || format == "tpl" || format == "j2" {
-        config.template = data
+        config.template = data;
+        config.template_name = name.to_string();
     } else {
         populate_ctx(&mut config.context, format, data)?;
     }
@@ -156,91 +294,369 @@ pub fn process_inputs(mut config: &mut Config, format: String, data: String) ->
 }
 
 pub fn populate_ctx(context: &mut Context, format: String, data: String) -> Result<()> {
-    match format.as_ref() {
-        "yaml" | "yml" => {
-            let value: serde_yaml::Value = serde_yaml::from_str(&data).context("Error parsing yaml")?;
-            let object = value.as_mapping().context("Error expected object in root of yaml file")?;
-            for (k, v) in object.iter() {
-                let k = k.as_str().context("Error decoding key of yaml, key is not a string")?;
-                context.insert(k, v);
-            }
-        }
-        "json" => {
-            let value = data.parse::<serde_json::Value>().context("Error parsing json")?;
-            let object = value.as_object().context("Error expected object in root of json file")?;
-            for (k, v) in object.iter() {
-                context.insert(k, v);
-            }
-        }
-        "toml" | "tml" => {
-            let value = data.parse::<toml::Value>().context("Error parsing toml")?;
-            let object = value.as_table().context("Error expected object in root of toml file")?;
-            for (k, v) in object.iter() {
-                context.insert(k, v);
-            }
-        }
-        "hcl" | "tfvars" | "tf" => {
-            let value = parse_hcl(&data).map_err(|e| anyhow!("Error {} parsing hcl/tf/tfvars", e))?;
-            let value = value.to_string().parse::<serde_json::Value>().context("Error parsing json of hcl/tf/tfvars")?;
-
-            let object = value
-                .as_object()
-                .context("Error expected object in root of hcl/tf/tfvars file")?;
-            for (k, v) in object.iter() {
-                context.insert(k, v);
-            }
-        }
-        _ => panic!("Format {} not recognized", format),
+    let value = formats::parse(&format, &data)?;
+    let object = value
+        .as_object()
+        .with_context(|| format!("Error expected object in root of {} file", format))?;
+    for (k, v) in object.iter() {
+        context.insert(k, v);
     }
     Ok(())
 }
 
 pub fn main() -> Result<()> {
-    let Config { template, context, print_ctx, out_file } = parse_args()?;
+    let config = parse_args()?;
+
+    if let Some(root) = &config.root {
+        sandbox::set_root(root)?;
+    }
+    required::set_strict(config.strict);
+    if let Some(dir) = &config.cache_dir {
+        cache::set_dir(dir, Duration::from_secs(config.cache_ttl))?;
+    }
 
-    if print_ctx {
-        println!("{}", context.into_json().to_string());
+    if let Some(query) = &config.query {
+        let query = Query::parse(query)?;
+        for result in query.run(&config.context.into_json())? {
+            let result = match result {
+                serde_json::Value::String(s) => s,
+                _ => formats::serialize(&result, &config.print_ctx_format).context("Error printing query result")?,
+            };
+            println!("{}", result.trim_end());
+        }
+        exit(0)
+    }
+
+    if config.print_ctx {
+        let ctx = formats::serialize(&config.context.into_json(), &config.print_ctx_format)
+            .context("Error printing context")?;
+        println!("{}", ctx.trim_end());
         exit(0)
     }
 
+    if config.watch {
+        return watch(config);
+    }
+
+    let only_if_changed = config.write_options.only_if_changed;
+    let written = render(config)?;
+    if only_if_changed && !written {
+        exit(UNCHANGED_EXIT_CODE)
+    }
+    Ok(())
+}
+
+fn modified_times(files: &[String]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| fs::metadata(file).and_then(|metadata| metadata.modified()).ok())
+        .collect()
+}
+
+pub fn watch(config: Config) -> Result<()> {
+    let mut files = config.watched_files.clone();
+    let mut mtimes = modified_times(&files);
+    let mut result = render(config);
+    loop {
+        match result {
+            Ok(_) => eprintln!("j2_render: rendered, watching {} files for changes", files.len()),
+            Err(e) => eprintln!("Error: {:?}", e),
+        }
+        while modified_times(&files) == mtimes {
+            thread::sleep(Duration::from_millis(500));
+        }
+        mtimes = modified_times(&files);
+        result = parse_args().and_then(|config| {
+            files = config.watched_files.clone();
+            mtimes = modified_times(&files);
+            render(config)
+        });
+    }
+}
+
+pub fn build_tera(template: &str, capabilities: &sandbox::Capabilities) -> Result<Tera> {
     let mut tera = Tera::default();
-    tera.add_raw_template("template", &template)
+    tera.add_raw_template("template", template)
         .context("Error loading template in engine")?;
 
-    tera.register_filter("bash", filters::bash);
-    tera.register_filter("sed", filters::sed);
-    tera.register_filter("glob", filters::file_glob);
-    tera.register_filter("read_file", filters::read_file);
-    tera.register_filter("file_name", filters::file_name);
-    tera.register_filter("file_dir", filters::file_dir);
+    if capabilities.exec {
+        tera.register_filter("bash", filters::bash);
+        tera.register_filter("sed", filters::sed);
+        tera.register_filter("exec", filters::exec);
+        tera.register_function("bash", functions::bash);
+        tera.register_function("exec", functions::exec);
+    } else {
+        tera.register_filter("bash", sandbox::denied_filter("bash", "exec"));
+        tera.register_filter("sed", sandbox::denied_filter("sed", "exec"));
+        tera.register_filter("exec", sandbox::denied_filter("exec", "exec"));
+        tera.register_function("bash", sandbox::denied_function("bash", "exec"));
+        tera.register_function("exec", sandbox::denied_function("exec", "exec"));
+    }
+
+    if capabilities.fs_read {
+        tera.register_filter("glob", filters::file_glob);
+        tera.register_filter("read_file", filters::read_file);
+        tera.register_filter("hash_file", filters::hash_file);
+        tera.register_filter("b64encode_file", filters::b64encode_file);
+        tera.register_filter("realpath", paths::realpath);
+        tera.register_filter("file_size", files::file_size);
+        tera.register_filter("file_mtime", files::file_mtime);
+        tera.register_filter("file_mode", files::file_mode);
+        tera.register_filter("read_lines", files::read_lines);
+        // alias of b64encode_file
+        tera.register_filter("read_bytes_b64", filters::b64encode_file);
+        tera.register_function("load", functions::load);
+        tera.register_function("load_glob", functions::load_glob);
+        tera.register_function("list_dir", files::list_dir);
+        tera.register_tester("file", testers::is_file);
+        tera.register_tester("directory", testers::is_directory);
+        tera.register_tester("exists", testers::exists);
+        tera.register_tester("symlink", testers::is_symlink);
+        tera.register_tester("executable", testers::is_executable);
+        tera.register_tester("readable", testers::is_readable);
+    } else {
+        tera.register_filter("glob", sandbox::denied_filter("glob", "fs-read"));
+        tera.register_filter("read_file", sandbox::denied_filter("read_file", "fs-read"));
+        tera.register_filter("hash_file", sandbox::denied_filter("hash_file", "fs-read"));
+        tera.register_filter("b64encode_file", sandbox::denied_filter("b64encode_file", "fs-read"));
+        tera.register_filter("realpath", sandbox::denied_filter("realpath", "fs-read"));
+        tera.register_filter("file_size", sandbox::denied_filter("file_size", "fs-read"));
+        tera.register_filter("file_mtime", sandbox::denied_filter("file_mtime", "fs-read"));
+        tera.register_filter("file_mode", sandbox::denied_filter("file_mode", "fs-read"));
+        tera.register_filter("read_lines", sandbox::denied_filter("read_lines", "fs-read"));
+        tera.register_filter("read_bytes_b64", sandbox::denied_filter("read_bytes_b64", "fs-read"));
+        tera.register_function("load", sandbox::denied_function("load", "fs-read"));
+        tera.register_function("load_glob", sandbox::denied_function("load_glob", "fs-read"));
+        tera.register_function("list_dir", sandbox::denied_function("list_dir", "fs-read"));
+        tera.register_tester("file", sandbox::denied_tester("file", "fs-read"));
+        tera.register_tester("directory", sandbox::denied_tester("directory", "fs-read"));
+        tera.register_tester("exists", sandbox::denied_tester("exists", "fs-read"));
+        tera.register_tester("symlink", sandbox::denied_tester("symlink", "fs-read"));
+        tera.register_tester("executable", sandbox::denied_tester("executable", "fs-read"));
+        tera.register_tester("readable", sandbox::denied_tester("readable", "fs-read"));
+    }
+
+    // get_env is a tera builtin, it is replaced only to deny it
+    if !capabilities.env {
+        tera.register_function("get_env", sandbox::denied_function("get_env", "env"));
+    }
+
+    tera.register_filter("file_name", paths::file_name);
+    tera.register_filter("file_dir", paths::file_dir);
+    tera.register_filter("extension", paths::extension);
+    tera.register_filter("stem", paths::stem);
+    tera.register_filter("path_join", paths::path_join);
+    tera.register_filter("normpath", paths::normpath);
+    tera.register_filter("abspath", paths::abspath);
+    tera.register_filter("relpath", paths::relpath);
+    tera.register_filter("expanduser", paths::expanduser);
     tera.register_filter("strip_line_breaks", filters::strip_line_breaks);
-    tera.register_filter("remove_extension", filters::remove_extension);
+    tera.register_filter("indent", filters::indent);
+    tera.register_filter("nindent", filters::nindent);
+    tera.register_filter("dedent", filters::dedent);
+    tera.register_filter("wrap", filters::wrap);
+    tera.register_filter("remove_extension", paths::remove_extension);
     tera.register_filter("b64decode", filters::b64decode);
     tera.register_filter("b64encode", filters::b64encode);
+    tera.register_filter("hex_encode", filters::hex_encode);
+    tera.register_filter("hex_decode", filters::hex_decode);
+    tera.register_filter("b32encode", filters::b32encode);
+    tera.register_filter("b32decode", filters::b32decode);
+    tera.register_filter("url_encode", filters::url_encode);
+    tera.register_filter("url_decode", filters::url_decode);
+    tera.register_filter("sha256", filters::sha256);
+    tera.register_filter("sha512", filters::sha512);
+    tera.register_filter("sha1", filters::sha1);
+    tera.register_filter("md5", filters::md5);
+    tera.register_filter("blake3", filters::blake3);
+    tera.register_filter("crc32", filters::crc32);
+    tera.register_filter("hmac", filters::hmac);
     tera.register_filter("str", filters::str);
-    tera.register_filter("to_json", filters::str);
+    tera.register_filter("to_json", filters::to_json);
+    tera.register_filter("to_yaml", filters::to_yaml);
+    tera.register_filter("to_toml", filters::to_toml);
+    tera.register_filter("to_hcl", filters::to_hcl);
+    tera.register_filter("to_env", filters::to_env);
     tera.register_filter("from_json", filters::from_json);
+    tera.register_filter("from_yaml", filters::from_yaml);
+    tera.register_filter("from_toml", filters::from_toml);
+    tera.register_filter("from_hcl", filters::from_hcl);
+    tera.register_filter("from_ini", filters::from_ini);
+    tera.register_filter("from_csv", filters::from_csv);
+    tera.register_filter("query", filters::query);
+    tera.register_filter("regex_replace", filters::regex_replace);
+    tera.register_filter("regex_match", filters::regex_match);
+    tera.register_filter("regex_find_all", filters::regex_find_all);
+    tera.register_filter("regex_captures", filters::regex_captures);
 
     tera.register_function("tab_all_lines", functions::tab_all_lines);
     tera.register_function("tab_all_lines_except_first", functions::tab_all_lines_except_first);
-    tera.register_function("bash", functions::bash);
     tera.register_function("str", functions::str);
     tera.register_function("to_json", functions::str);
     tera.register_function("from_json", functions::from_json);
+    tera.register_function("query", functions::query);
+    tera.register_function("output_file", functions::output_file);
 
-    tera.register_tester("file", testers::is_file);
-    tera.register_tester("directory", testers::is_directory);
+    tera.register_tester("matches_regex", testers::matches_regex);
 
-    let rendered = tera.render("template", &context).context("Error rendering template")?;
+    // helm sprig compatibility
+    tera.register_filter("quote", sprig::quote);
+    tera.register_filter("squote", sprig::squote);
+    tera.register_filter("toYaml", sprig::to_yaml);
+    tera.register_filter("trimPrefix", sprig::trim_prefix);
+    tera.register_filter("trimSuffix", sprig::trim_suffix);
+    tera.register_filter("merge", sprig::merge);
+    tera.register_filter("hasKey", sprig::has_key);
+    tera.register_filter("pluck", sprig::pluck);
+    tera.register_filter("ternary", sprig::ternary);
+    tera.register_function("semverCompare", sprig::semver_compare);
+    tera.register_function("uuidv4", sprig::uuidv4);
+    tera.register_function("randAlphaNum", sprig::rand_alpha_num);
+    tera.register_function("dict", sprig::dict);
 
-    if let Some(filepath) = out_file {
-        let mut file = fs::File::create(&filepath).context("Error creating output file")?;
-        file.write_all(rendered.as_ref()).context("Error writing to output file")?;
+    // ansible compatibility
+    tera.register_filter("to_nice_yaml", filters::to_yaml);
+    tera.register_filter("to_nice_json", ansible::to_nice_json);
+    tera.register_filter("combine", ansible::combine);
+    tera.register_filter("dict2items", ansible::dict2items);
+    tera.register_filter("items2dict", ansible::items2dict);
+    tera.register_filter("mandatory", ansible::mandatory);
+    tera.register_filter("ipaddr", ansible::ipaddr);
+    tera.register_filter("basename", ansible::basename);
+    tera.register_filter("dirname", ansible::dirname);
+    tera.register_filter("splitext", ansible::splitext);
+    tera.register_filter("bool", ansible::to_bool);
+    tera.register_filter("flatten", ansible::flatten);
+    tera.register_filter("difference", ansible::difference);
+    tera.register_filter("zip", ansible::zip);
+
+    Ok(tera)
+}
+
+/// renders the template, returns false if --only-if-changed skipped writing the output
+pub fn render(config: Config) -> Result<bool> {
+    let capabilities = if config.sandbox { config.allowed } else { sandbox::Capabilities::all() };
+    let Config { template, template_name, context, out_file, out_dir, foreach, write_options, .. } = config;
+
+    let mut tera = build_tera(&template, &capabilities)?;
+    // the filter is also the helm sprig `required`
+    tera.register_filter("required", required::filter(template_name.clone()));
+    cache::clear();
+    output::reset_marker();
+    required::take_failures();
+
+    // rendered before writing anything so missing required values in --strict leave every output untouched
+    let mut renders = vec![];
+    let mut failures = vec![];
+    let create_dirs = foreach.is_some() && !write_options.check;
+    if let Some(foreach) = foreach {
+        let out_file = out_file.ok_or(anyhow!("error --foreach requires --out/-o path template"))?;
+        tera.add_raw_template("out_file", &out_file)
+            .context("Error loading --out/-o path template in engine")?;
+
+        let path = if foreach.starts_with('.') { foreach.clone() } else { format!(".{}", foreach) };
+        let items = Query::parse(&path)?.run_to_value(&context.clone().into_json())?;
+        let items = items
+            .as_array()
+            .ok_or(anyhow!("Error --foreach {} is not a list in the context", foreach))?;
+
+        for item in items.iter() {
+            let mut context = context.clone();
+            context.insert("item", item);
+            let filepath = tera
+                .render("out_file", &context)
+                .context("Error rendering --out/-o path template")?;
+            // required looks keys up in the context of each render so missing keys reach it
+            let json = context.clone().into_json();
+            tera.register_function("required", required::function(json, template_name.clone()));
+            let rendered = tera
+                .render("template", &context)
+                .with_context(|| format!("Error rendering template {} for {}", template_name, filepath))?;
+            for failure in required::take_failures() {
+                failures.push(format!("{} for {}", failure, filepath));
+            }
+            renders.push((rendered, Some(filepath)));
+        }
     } else {
+        let json = context.clone().into_json();
+        tera.register_function("required", required::function(json, template_name.clone()));
+        let rendered = tera
+            .render("template", &context)
+            .with_context(|| format!("Error rendering template {}", template_name))?;
+        failures.extend(required::take_failures());
+        renders.push((rendered, out_file));
+    }
+
+    if !failures.is_empty() {
+        for failure in failures.iter() {
+            eprintln!("{}", failure);
+        }
+        return Err(anyhow!("Error {} required values are missing", failures.len()));
+    }
+
+    // output_file paths are checked for every render before writing anything too
+    let mut outputs = vec![];
+    for (rendered, filepath) in renders {
+        let (head, files) = split_rendered(&rendered, out_dir.as_deref())?;
+        outputs.push((head, files, filepath));
+    }
+
+    let mut outcomes = vec![];
+    for (head, files, filepath) in outputs {
+        if let (Some(filepath), true) = (&filepath, create_dirs) {
+            create_parent_dir(filepath)?;
+        }
+        outcomes.extend(write_rendered(&head, &files, filepath.as_deref(), &write_options)?);
+    }
+
+    let differs = outcomes.iter().filter(|outcome| **outcome == output::Outcome::Differs).count();
+    if differs > 0 {
+        return Err(anyhow!("Error {} output files differ from rendered template", differs));
+    }
+    Ok(outcomes.is_empty() || outcomes.contains(&output::Outcome::Written))
+}
+
+fn create_parent_dir(filepath: &str) -> Result<()> {
+    if let Some(parent) = Path::new(filepath).parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Error creating directory for {}", filepath))?;
+    }
+    Ok(())
+}
+
+/// splits the rendered template in the content before the first output_file and the files under out_dir
+fn split_rendered(rendered: &str, out_dir: Option<&str>) -> Result<(String, Vec<(String, String)>)> {
+    let (head, files) = output::split_files(rendered);
+    if files.is_empty() {
+        return Ok((head, files));
+    }
+    let out_dir = out_dir.ok_or(anyhow!("Error template uses output_file but --out-dir/-d not provided"))?;
+    let mut filepaths = vec![];
+    for (path, contents) in files {
+        filepaths.push((output::out_dir_path(out_dir, &path)?, contents));
+    }
+    Ok((head, filepaths))
+}
+
+fn write_rendered(
+    head: &str,
+    files: &[(String, String)],
+    out_file: Option<&str>,
+    write_options: &output::WriteOptions,
+) -> Result<Vec<output::Outcome>> {
+    let mut outcomes = vec![];
+    if let Some(filepath) = out_file {
+        outcomes.push(output::emit(filepath, head, write_options)?);
+    } else if !write_options.check && (files.is_empty() || !head.trim().is_empty()) {
         io::stdout()
-            .write_all(rendered.as_ref())
+            .write_all(head.as_ref())
             .context("Error writing to stdout")?;
     }
 
-    Ok(())
+    for (filepath, contents) in files.iter() {
+        if !write_options.check {
+            create_parent_dir(filepath)?;
+        }
+        outcomes.push(output::emit(filepath, contents, write_options)?);
+    }
+    Ok(outcomes)
 }
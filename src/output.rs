@@ -0,0 +1,253 @@
+use anyhow::{anyhow, Context, Result};
+use similar::TextDiff;
+use std::ffi::OsStr;
+use std::fs::{self, Metadata, Permissions};
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::fs::{chown, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+pub fn read_existing(filepath: &str) -> Result<Option<String>> {
+    match fs::read_to_string(filepath) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Error reading output file {}", filepath)),
+    }
+}
+
+/// prints an unified diff if the file differs from the rendered template, returns true if equal
+pub fn check(filepath: &str, rendered: &str) -> Result<bool> {
+    let existing = read_existing(filepath)?;
+    let current = existing.as_deref().unwrap_or("");
+    if existing.is_some() && current == rendered {
+        return Ok(true);
+    }
+    let diff = TextDiff::from_lines(current, rendered);
+    print!(
+        "{}",
+        diff.unified_diff()
+            .header(filepath, &format!("{} (rendered)", filepath))
+    );
+    eprintln!("{}: {}", filepath, if existing.is_some() { "differs" } else { "missing" });
+    Ok(false)
+}
+
+#[derive(Default)]
+pub struct WriteOptions {
+    pub mode: Option<u32>,
+    pub preserve: bool,
+    pub backup: bool,
+    pub check: bool,
+    pub only_if_changed: bool,
+}
+
+#[derive(PartialEq)]
+pub enum Outcome {
+    Written,
+    Unchanged,
+    Differs,
+}
+
+/// checks or writes the rendered contents to filepath as requested by options
+pub fn emit(filepath: &str, contents: &str, options: &WriteOptions) -> Result<Outcome> {
+    if options.check {
+        return Ok(if check(filepath, contents)? { Outcome::Unchanged } else { Outcome::Differs });
+    }
+    if options.only_if_changed {
+        let written = write_if_changed(filepath, contents, options)?;
+        eprintln!("{}: {}", filepath, if written { "written" } else { "unchanged" });
+        return Ok(if written { Outcome::Written } else { Outcome::Unchanged });
+    }
+    write(filepath, contents, options)?;
+    Ok(Outcome::Written)
+}
+
+static MARKER: Mutex<String> = Mutex::new(String::new());
+
+/// changes the random token of the output_file markers, called before each render
+/// so context values and command outputs can't contain a marker and create files
+pub fn reset_marker() {
+    *MARKER.lock().expect("marker lock poisoned") = format!("\u{0}j2_render:output_file:{}:", Uuid::new_v4().simple());
+}
+
+fn marker() -> String {
+    MARKER.lock().expect("marker lock poisoned").clone()
+}
+
+pub fn output_file_marker(path: &str) -> String {
+    format!("{}{}\u{0}", marker(), path)
+}
+
+/// splits the rendered template by output_file markers,
+/// returns the content before the first marker and the content of each file in order
+pub fn split_files(rendered: &str) -> (String, Vec<(String, String)>) {
+    let marker = marker();
+    if marker.is_empty() {
+        return (rendered.to_string(), vec![]);
+    }
+    let mut sections = rendered.split(marker.as_str());
+    let head = sections.next().unwrap_or("").to_string();
+    let mut files: Vec<(String, String)> = vec![];
+    for section in sections {
+        let mut parts = section.splitn(2, '\u{0}');
+        let path = parts.next().unwrap_or("").to_string();
+        let contents = parts.next().unwrap_or("");
+        if let Some((_, existing)) = files.iter_mut().find(|(p, _)| *p == path) {
+            existing.push_str(contents);
+        } else {
+            files.push((path, contents.to_string()));
+        }
+    }
+    (head, files)
+}
+
+/// resolves the symlinks of the longest existing ancestor of path, the rest is kept as is
+fn resolve(path: &Path) -> Result<PathBuf> {
+    for ancestor in path.ancestors().filter(|ancestor| *ancestor != Path::new("")) {
+        if let Ok(resolved) = fs::canonicalize(ancestor) {
+            return Ok(resolved.join(path.strip_prefix(ancestor)?));
+        }
+    }
+    let current_dir = std::env::current_dir().context("Error getting current dir")?;
+    Ok(current_dir.join(path))
+}
+
+/// the path of an output_file inside out_dir, fails if it is absolute, has .. or a symlink leads out of out_dir
+pub fn out_dir_path(out_dir: &str, path: &str) -> Result<String> {
+    let relative = Path::new(path);
+    if path.is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+        return Err(anyhow!("Error output_file path {} must be relative to --out-dir and can't contain ..", path));
+    }
+    let filepath = Path::new(out_dir).join(relative);
+    if !resolve(&filepath)?.starts_with(resolve(Path::new(out_dir))?) {
+        return Err(anyhow!("Error output_file path {} resolves outside of --out-dir through a symlink", path));
+    }
+    Ok(filepath.to_str().ok_or(anyhow!("Error decoding output file path {}", path))?.to_string())
+}
+
+pub fn parse_mode(mode: &str) -> Result<u32> {
+    let mode = u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+        .with_context(|| format!("Error parsing mode {}, expected octal like 0600", mode))?;
+    if mode > 0o7777 {
+        return Err(anyhow!("Error mode {:o} out of range, expected up to 7777", mode));
+    }
+    Ok(mode)
+}
+
+/// replaces filepath atomically keeping the mode and, when permitted, the owner of the existing file,
+/// symlinks are followed and targets that are not regular files, like devices, are written in place
+pub fn write(filepath: &str, contents: &str, options: &WriteOptions) -> Result<()> {
+    let path = fs::canonicalize(filepath).unwrap_or_else(|_| PathBuf::from(filepath));
+    let existing = fs::metadata(&path).ok();
+    if let Some(metadata) = existing.as_ref().filter(|metadata| !metadata.is_file()) {
+        if metadata.is_dir() {
+            return Err(anyhow!("Error output file {} is a directory", filepath));
+        }
+        return write_in_place(&path, filepath, contents);
+    }
+
+    let file_name = path
+        .file_name()
+        .and_then(OsStr::to_str)
+        .ok_or(anyhow!("Error output file {} has no file name", filepath))?;
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    // a random name so a temporary file left by a killed run doesn't collide with the next one
+    let tmp_path = dir.join(format!(".{}.{}.tmp", file_name, Uuid::new_v4().simple()));
+
+    let mode = options
+        .mode
+        .or_else(|| existing.as_ref().map(|metadata| metadata.permissions().mode() & 0o7777));
+
+    let result = write_tmp(&tmp_path, contents, mode, existing.as_ref(), options.preserve).and_then(|()| {
+        backup(&path, filepath, options, existing.is_some())?;
+        fs::rename(&tmp_path, &path).with_context(|| format!("Error replacing output file {}", filepath))
+    });
+    match result {
+        Err(e) if existing.is_some() && is_permission_denied(&e) && !tmp_path.exists() => {
+            // the dir is not writable but the file is, like files in /etc owned by a service user
+            eprintln!("{}: dir not writable, written in place instead of atomically", filepath);
+            backup(&path, filepath, options, true)?;
+            write_in_place(&path, filepath, contents)?;
+            if let Some(mode) = options.mode {
+                fs::set_permissions(&path, Permissions::from_mode(mode)).context("Error setting output file mode")?;
+            }
+            Ok(())
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            Err(e)
+        }
+        Ok(()) => Ok(()),
+    }
+}
+
+fn is_permission_denied(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<io::Error>()
+        .map(|e| e.kind() == ErrorKind::PermissionDenied)
+        .unwrap_or(false)
+}
+
+fn backup(path: &Path, filepath: &str, options: &WriteOptions, exists: bool) -> Result<()> {
+    if options.backup && exists {
+        let backup_path = format!("{}.bak", filepath);
+        fs::copy(path, &backup_path).with_context(|| format!("Error creating backup file {}", backup_path))?;
+    }
+    Ok(())
+}
+
+fn write_in_place(path: &Path, filepath: &str, contents: &str) -> Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(path)
+        .with_context(|| format!("Error opening output file {}", filepath))?;
+    file.write_all(contents.as_ref())
+        .with_context(|| format!("Error writing to output file {}", filepath))
+}
+
+/// writes the file only if its contents differ, returns true if the file was written
+pub fn write_if_changed(filepath: &str, contents: &str, options: &WriteOptions) -> Result<bool> {
+    if read_existing(filepath)?.as_deref() == Some(contents) {
+        return Ok(false);
+    }
+    write(filepath, contents, options)?;
+    Ok(true)
+}
+
+/// with preserve failing to keep the owner of the existing file is an error, without it is ignored
+fn write_tmp(
+    tmp_path: &Path,
+    contents: &str,
+    mode: Option<u32>,
+    existing: Option<&Metadata>,
+    preserve: bool,
+) -> Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode.unwrap_or(0o666))
+        .open(tmp_path)
+        .with_context(|| format!("Error creating temporary output file {}", tmp_path.display()))?;
+    file.write_all(contents.as_ref())
+        .context("Error writing to output file")?;
+    file.sync_all().context("Error syncing output file")?;
+    if let Some(mode) = mode {
+        // umask is applied on creation, set the exact mode requested
+        fs::set_permissions(tmp_path, Permissions::from_mode(mode)).context("Error setting output file mode")?;
+    }
+    if let Some(metadata) = existing {
+        let created = file.metadata().context("Error reading output file metadata")?;
+        if created.uid() != metadata.uid() || created.gid() != metadata.gid() {
+            let owned = chown(tmp_path, Some(metadata.uid()), Some(metadata.gid()));
+            if preserve {
+                owned.context("Error preserving owner of output file")?;
+            }
+        }
+    }
+    Ok(())
+}
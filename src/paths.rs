@@ -0,0 +1,173 @@
+use crate::sandbox;
+use std::collections::HashMap;
+use std::env;
+use std::path::{Component, Path, PathBuf};
+use tera::{Result, Value};
+
+fn path_arg<'a>(name: &str, piped_arg: &'a Value) -> Result<&'a Path> {
+    if let Value::String(path) = piped_arg {
+        return Ok(Path::new(path));
+    } else {
+        return Err(format!("{}: Invalid type, expected string", name).into());
+    }
+}
+
+fn path_value(name: &str, path: &Path) -> Result<Value> {
+    match path.to_str() {
+        Some(path) => return Ok(Value::String(path.to_string())),
+        None => return Err(format!("{}: error decoding path {}", name, path.display()).into()),
+    }
+}
+
+/// removes `.` and `..` without touching the filesystem, like python os.path.normpath
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut components = vec![];
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match components.last() {
+                Some(Component::Normal(_)) => {
+                    components.pop();
+                }
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => components.push(component),
+            },
+            _ => components.push(component),
+        }
+    }
+    if components.is_empty() {
+        return PathBuf::from(".");
+    }
+    return components.iter().collect();
+}
+
+fn absolute(name: &str, path: &Path) -> Result<PathBuf> {
+    let current_dir = env::current_dir().map_err(|e| format!("{}: error getting current dir : {}", name, e))?;
+    return Ok(normalize(&current_dir.join(path)));
+}
+
+pub fn file_name(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    let path = path_arg("file_name", piped_arg)?;
+    match path.file_name() {
+        Some(file_name) => return path_value("file_name", Path::new(file_name)),
+        None => return Err("file_name: error extracting filename : path is root, no filename".into()),
+    }
+}
+
+pub fn file_dir(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    let path = path_arg("file_dir", piped_arg)?;
+    match path.parent() {
+        Some(parent) => return path_value("file_dir", parent),
+        None => return Err("file_dir: error extracting filename : path is root, no filename".into()),
+    }
+}
+
+/// removes only the last extension of the file name, the directories are kept as they are
+pub fn remove_extension(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    let path = path_arg("remove_extension", piped_arg)?;
+    return path_value("remove_extension", &path.with_extension(""));
+}
+
+/// the last extension of the file name without the dot, empty if it has none
+pub fn extension(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    let path = path_arg("extension", piped_arg)?;
+    let extension = path.extension().map(Path::new).unwrap_or(Path::new(""));
+    return path_value("extension", extension);
+}
+
+/// the file name without its last extension
+pub fn stem(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    let path = path_arg("stem", piped_arg)?;
+    match path.file_stem() {
+        Some(stem) => return path_value("stem", Path::new(stem)),
+        None => return Err("stem: error extracting file stem : path has no filename".into()),
+    }
+}
+
+/// joins the piped list of paths or the piped path with path, a string or a list
+pub fn path_join(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    let mut parts = match piped_arg {
+        Value::String(_) => vec![piped_arg],
+        Value::Array(items) => items.iter().collect(),
+        _ => return Err("path_join: Invalid type, expected string or list of strings".into()),
+    };
+    match args.get("path") {
+        Some(Value::Array(items)) => parts.extend(items.iter()),
+        Some(path) => parts.push(path),
+        None => {}
+    }
+    let mut joined = PathBuf::new();
+    for part in parts {
+        if let Value::String(part) = part {
+            joined.push(part);
+        } else {
+            return Err("path_join: Invalid type for path, expected string".into());
+        }
+    }
+    return path_value("path_join", &joined);
+}
+
+pub fn normpath(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    let path = path_arg("normpath", piped_arg)?;
+    return path_value("normpath", &normalize(path));
+}
+
+/// relative paths are joined to the current dir and normalized without touching the filesystem
+pub fn abspath(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    let path = path_arg("abspath", piped_arg)?;
+    return path_value("abspath", &absolute("abspath", path)?);
+}
+
+/// the path relative to base, the current dir by default
+pub fn relpath(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    let path = absolute("relpath", path_arg("relpath", piped_arg)?)?;
+    let base = match args.get("base") {
+        Some(Value::String(base)) => absolute("relpath", Path::new(base))?,
+        None => absolute("relpath", Path::new("."))?,
+        _ => return Err("relpath: Invalid type for arg base, expected string".into()),
+    };
+    let path_components: Vec<_> = path.components().collect();
+    let base_components: Vec<_> = base.components().collect();
+    let common = path_components
+        .iter()
+        .zip(base_components.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative = PathBuf::new();
+    for _ in common..base_components.len() {
+        relative.push("..");
+    }
+    for component in path_components[common..].iter() {
+        relative.push(component);
+    }
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    return path_value("relpath", &relative);
+}
+
+/// replaces a leading `~` with the HOME env var
+pub fn expanduser(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    let path = path_arg("expanduser", piped_arg)?;
+    let home = match env::var("HOME") {
+        Ok(home) => home,
+        Err(_) => return path_value("expanduser", path),
+    };
+    match path.strip_prefix("~") {
+        Ok(rest) => return path_value("expanduser", &Path::new(&home).join(rest)),
+        Err(_) => return path_value("expanduser", path),
+    }
+}
+
+/// resolves symlinks of an existing path
+pub fn realpath(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::String(path) = piped_arg {
+        let path = sandbox::check_path("realpath", path)?;
+        let resolved = path
+            .canonicalize()
+            .map_err(|e| format!("realpath: error resolving {} : {}", path.display(), e))?;
+        return path_value("realpath", &resolved);
+    } else {
+        return Err("realpath: Invalid type, expected string".into());
+    }
+}
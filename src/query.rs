@@ -0,0 +1,259 @@
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+#[derive(Debug)]
+enum Segment {
+    Key(String),
+    Index(i64),
+    Iterate,
+}
+
+pub struct Query {
+    stages: Vec<Vec<Segment>>,
+}
+
+impl Query {
+    /// Parses a jq like expression, supports `.`, `.key`, `."key"`, `.["key"]`, `.[index]`, `.[]` and `|`
+    pub fn parse(expr: &str) -> Result<Query> {
+        let mut stages = vec![];
+        for stage in split_stages(expr) {
+            stages.push(parse_stage(stage.trim()).map_err(|e| anyhow!("Error parsing query {} : {}", expr, e))?);
+        }
+        Ok(Query { stages })
+    }
+
+    /// true if the query can yield more than one result
+    pub fn iterates(&self) -> bool {
+        self.stages
+            .iter()
+            .any(|stage| stage.iter().any(|segment| matches!(segment, Segment::Iterate)))
+    }
+
+    pub fn run(&self, value: &Value) -> Result<Vec<Value>> {
+        let mut results = vec![value.clone()];
+        for stage in self.stages.iter() {
+            for segment in stage.iter() {
+                let mut next = vec![];
+                for result in results.iter() {
+                    apply(segment, result, &mut next)?;
+                }
+                results = next;
+            }
+        }
+        Ok(results)
+    }
+
+    /// runs the query returning the value if it has a single result or an array of results if it iterates
+    pub fn run_to_value(&self, value: &Value) -> Result<Value> {
+        let mut results = self.run(value)?;
+        if !self.iterates() && results.len() == 1 {
+            return Ok(results.pop().expect(""));
+        }
+        Ok(Value::Array(results))
+    }
+}
+
+fn apply(segment: &Segment, value: &Value, out: &mut Vec<Value>) -> Result<()> {
+    match (segment, value) {
+        (Segment::Key(key), Value::Object(object)) => out.push(object.get(key).cloned().unwrap_or(Value::Null)),
+        (Segment::Key(_), Value::Null) => out.push(Value::Null),
+        (Segment::Key(key), _) => return Err(anyhow!("Error cannot index {} with key {}", type_name(value), key)),
+        (Segment::Index(idx), Value::Array(items)) => {
+            let idx = if *idx < 0 { items.len() as i64 + idx } else { *idx };
+            let item = if idx < 0 { None } else { items.get(idx as usize) };
+            out.push(item.cloned().unwrap_or(Value::Null));
+        }
+        (Segment::Index(_), Value::Null) => out.push(Value::Null),
+        (Segment::Index(idx), _) => return Err(anyhow!("Error cannot index {} with {}", type_name(value), idx)),
+        (Segment::Iterate, Value::Array(items)) => out.extend(items.iter().cloned()),
+        (Segment::Iterate, Value::Object(object)) => out.extend(object.values().cloned()),
+        (Segment::Iterate, _) => return Err(anyhow!("Error cannot iterate over {}", type_name(value))),
+    }
+    Ok(())
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// splits the expression by the `|` that are not inside quoted keys
+fn split_stages(expr: &str) -> Vec<&str> {
+    let mut stages = vec![];
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (pos, c) in expr.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '|' if !quoted => {
+                stages.push(&expr[start..pos]);
+                start = pos + 1;
+            }
+            _ => {}
+        }
+    }
+    stages.push(&expr[start..]);
+    stages
+}
+
+fn parse_stage(stage: &str) -> Result<Vec<Segment>> {
+    let chars: Vec<char> = stage.chars().collect();
+    if chars.first() != Some(&'.') {
+        return Err(anyhow!("expected expression starting with '.'"));
+    }
+    let mut segments = vec![];
+    let mut pos = 0;
+    while pos < chars.len() {
+        match chars[pos] {
+            '.' => {
+                pos += 1;
+                if pos >= chars.len() || chars[pos] == '[' {
+                    continue;
+                }
+                if chars[pos] == '"' {
+                    let (key, end) = parse_quoted(&chars, pos)?;
+                    segments.push(Segment::Key(key));
+                    pos = end;
+                } else {
+                    let start = pos;
+                    while pos < chars.len() && (chars[pos].is_alphanumeric() || chars[pos] == '_' || chars[pos] == '-') {
+                        pos += 1;
+                    }
+                    if start == pos {
+                        return Err(anyhow!("expected key after '.' at position {}", start));
+                    }
+                    segments.push(Segment::Key(chars[start..pos].iter().collect()));
+                }
+            }
+            '[' => {
+                pos += 1;
+                if chars.get(pos) == Some(&']') {
+                    segments.push(Segment::Iterate);
+                } else if chars.get(pos) == Some(&'"') {
+                    let (key, end) = parse_quoted(&chars, pos)?;
+                    segments.push(Segment::Key(key));
+                    pos = end;
+                } else {
+                    let start = pos;
+                    while pos < chars.len() && chars[pos] != ']' {
+                        pos += 1;
+                    }
+                    let idx: String = chars[start..pos].iter().collect();
+                    let idx = idx
+                        .trim()
+                        .parse::<i64>()
+                        .map_err(|_| anyhow!("expected index, key or nothing inside [] found {}", idx))?;
+                    segments.push(Segment::Index(idx));
+                }
+                if chars.get(pos) != Some(&']') {
+                    return Err(anyhow!("expected ']' at position {}", pos));
+                }
+                pos += 1;
+            }
+            c => return Err(anyhow!("unexpected character {} at position {}", c, pos)),
+        }
+    }
+    Ok(segments)
+}
+
+fn parse_quoted(chars: &[char], start: usize) -> Result<(String, usize)> {
+    let mut pos = start + 1;
+    let mut key = String::new();
+    while pos < chars.len() && chars[pos] != '"' {
+        if chars[pos] == '\\' && pos + 1 < chars.len() {
+            pos += 1;
+        }
+        key.push(chars[pos]);
+        pos += 1;
+    }
+    if pos >= chars.len() {
+        return Err(anyhow!("unterminated string starting at position {}", start));
+    }
+    Ok((key, pos + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn run(expr: &str, value: &Value) -> Vec<Value> {
+        Query::parse(expr).unwrap().run(value).unwrap()
+    }
+
+    #[test]
+    fn parses_segments() {
+        let segments = parse_stage(r#".a."b c"["d"][-1][]"#).unwrap();
+        let segments: Vec<_> = segments.iter().map(|segment| format!("{:?}", segment)).collect();
+        assert_eq!(
+            segments,
+            vec![r#"Key("a")"#, r#"Key("b c")"#, r#"Key("d")"#, "Index(-1)", "Iterate"]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_stages() {
+        assert!(parse_stage("a").is_err());
+        assert!(parse_stage(r#"."a"#).is_err());
+        assert!(parse_stage(".[x]").is_err());
+        assert!(parse_stage(".[0").is_err());
+    }
+
+    #[test]
+    fn negative_indexes_count_from_the_end() {
+        let value = json!({"list": [1, 2, 3]});
+        assert_eq!(run(".list[-1]", &value), vec![json!(3)]);
+        assert_eq!(run(".list[-3]", &value), vec![json!(1)]);
+        assert_eq!(run(".list[-4]", &value), vec![Value::Null]);
+        assert_eq!(run(".list[5]", &value), vec![Value::Null]);
+    }
+
+    #[test]
+    fn iterates_over_objects_values() {
+        let value = json!({"servers": {"a": {"port": 1}, "b": {"port": 2}}});
+        assert_eq!(run(".servers[].port", &value), vec![json!(1), json!(2)]);
+        assert_eq!(run(".servers[] | .port", &value), vec![json!(1), json!(2)]);
+    }
+
+    #[test]
+    fn quoted_keys_can_contain_pipes_and_dots() {
+        let value = json!({"a|b": {"c.d": 1}, "e\"f": 2});
+        assert_eq!(run(r#"."a|b"."c.d""#, &value), vec![json!(1)]);
+        assert_eq!(run(r#".["a|b"] | ."c.d""#, &value), vec![json!(1)]);
+        assert_eq!(run(r#"."e\"f""#, &value), vec![json!(2)]);
+    }
+
+    #[test]
+    fn missing_paths_are_null() {
+        let value = json!({"a": null});
+        assert_eq!(run(".missing.key", &value), vec![Value::Null]);
+        assert_eq!(run(".a.b[0]", &value), vec![Value::Null]);
+    }
+
+    #[test]
+    fn wrong_types_are_errors() {
+        let value = json!({"s": "text", "n": 1});
+        assert!(Query::parse(".s.key").unwrap().run(&value).is_err());
+        assert!(Query::parse(".n[0]").unwrap().run(&value).is_err());
+        assert!(Query::parse(".n[]").unwrap().run(&value).is_err());
+    }
+
+    #[test]
+    fn single_results_are_not_wrapped() {
+        let value = json!({"a": [1, 2]});
+        assert_eq!(Query::parse(".a[0]").unwrap().run_to_value(&value).unwrap(), json!(1));
+        assert_eq!(
+            Query::parse(".a[]").unwrap().run_to_value(&value).unwrap(),
+            json!([1, 2])
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use crate::query::Query;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use tera::{Result, Value};
+
+static STRICT: AtomicBool = AtomicBool::new(false);
+static FAILURES: OnceLock<Mutex<Vec<String>>> = OnceLock::new();
+
+fn failures() -> &'static Mutex<Vec<String>> {
+    FAILURES.get_or_init(|| Mutex::new(vec![]))
+}
+
+/// with strict the failures are collected and reported after rendering instead of failing on the first one
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+/// returns the collected failures and forgets them, called after each render
+pub fn take_failures() -> Vec<String> {
+    return failures().lock().expect("required lock poisoned").drain(..).collect();
+}
+
+fn is_missing(value: &Value) -> bool {
+    return value.is_null() || value == "";
+}
+
+fn msg_arg(args: &HashMap<String, Value>, default: String) -> Result<String> {
+    match args.get("msg") {
+        Some(Value::String(msg)) => return Ok(msg.clone()),
+        None => return Ok(default),
+        _ => return Err("required: Invalid type for arg msg, expected string".into()),
+    }
+}
+
+/// tera doesn't give the location of filter and function calls, only the template is reported
+fn fail(template_name: &str, msg: String) -> Result<Value> {
+    let failure = format!("{}: {}", template_name, msg);
+    if !STRICT.load(Ordering::Relaxed) {
+        return Err(failure.into());
+    }
+    failures().lock().expect("required lock poisoned").push(failure);
+    return Ok(Value::String(String::new()));
+}
+
+/// returns the piped value, fails with msg when it is null or empty, like `x | required(msg="x is required")`
+pub fn filter(template_name: String) -> impl Fn(&Value, &HashMap<String, Value>) -> Result<Value> + Sync + Send {
+    move |piped_arg: &Value, args: &HashMap<String, Value>| {
+        let msg = msg_arg(args, "value is required".to_string())?;
+        if !is_missing(piped_arg) {
+            return Ok(piped_arg.clone());
+        }
+        return fail(&template_name, msg);
+    }
+}
+
+/// like the filter with the value arg, with the key arg the value is looked up in the context instead
+/// so keys missing from it can be required too, key is a path like `db.host` or a query like `.servers[0].name`
+pub fn function(
+    context: Value,
+    template_name: String,
+) -> impl Fn(&HashMap<String, Value>) -> Result<Value> + Sync + Send {
+    move |args: &HashMap<String, Value>| {
+        let (value, default_msg) = match (args.get("value"), args.get("key")) {
+            (Some(value), None) => (value.clone(), "value is required".to_string()),
+            (None, Some(Value::String(key))) => {
+                let path = if key.starts_with('.') { key.clone() } else { format!(".{}", key) };
+                let query = Query::parse(&path).map_err(|e| format!("required: {}", e))?;
+                // indexing a value of another type, like a key of a string, means the key is missing too
+                (query.run_to_value(&context).unwrap_or(Value::Null), format!("{} is required", key))
+            }
+            (None, Some(_)) => return Err("required: Invalid type for arg key, expected string".into()),
+            _ => return Err("required: expected one of the args value or key".into()),
+        };
+        let msg = msg_arg(args, default_msg)?;
+        if !is_missing(&value) {
+            return Ok(value);
+        }
+        return fail(&template_name, msg);
+    }
+}
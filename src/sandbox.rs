@@ -0,0 +1,107 @@
+use crate::paths;
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tera::Value;
+
+static ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+pub const CAPABILITIES: &[&str] = &["exec", "fs-read", "env"];
+
+pub struct Capabilities {
+    pub exec: bool,
+    pub fs_read: bool,
+    pub env: bool,
+}
+
+impl Capabilities {
+    pub fn all() -> Capabilities {
+        Capabilities { exec: true, fs_read: true, env: true }
+    }
+
+    pub fn none() -> Capabilities {
+        Capabilities { exec: false, fs_read: false, env: false }
+    }
+
+    pub fn allow(&mut self, list: &str) -> Result<()> {
+        for capability in list.split(',').map(str::trim) {
+            match capability {
+                "exec" => self.exec = true,
+                "fs-read" => self.fs_read = true,
+                "env" => self.env = true,
+                _ => {
+                    return Err(anyhow!(
+                        "Error capability {} not recognized, expected one of {}",
+                        capability,
+                        CAPABILITIES.join(",")
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn set_root(root: &str) -> Result<()> {
+    let root = PathBuf::from(root)
+        .canonicalize()
+        .with_context(|| format!("Error resolving --root {}", root))?;
+    if let Some(current) = ROOT.get() {
+        if *current != root {
+            return Err(anyhow!("Error --root already set to {}", current.display()));
+        }
+        return Ok(());
+    }
+    ROOT.set(root).map_err(|_| anyhow!("Error setting --root"))
+}
+
+/// resolves symlinks of existing paths, paths that don't exist are normalized without touching the filesystem
+fn resolve(path: &str) -> PathBuf {
+    if let Ok(resolved) = Path::new(path).canonicalize() {
+        return resolved;
+    }
+    paths::normalize(&env::current_dir().unwrap_or_default().join(path))
+}
+
+/// true if the path is inside --root or --root was not provided
+pub fn is_allowed(path: &str) -> bool {
+    match ROOT.get() {
+        Some(root) => resolve(path).starts_with(root),
+        None => true,
+    }
+}
+
+/// fails if the path is outside --root
+pub fn check_path(name: &str, path: &str) -> tera::Result<PathBuf> {
+    if !is_allowed(path) {
+        return Err(format!("{}: path {} is outside of --root", name, path).into());
+    }
+    Ok(PathBuf::from(path))
+}
+
+fn denied(name: &str, capability: &str) -> tera::Error {
+    format!("{}: disabled by --sandbox, enable it with --allow {}", name, capability).into()
+}
+
+pub fn denied_filter(
+    name: &'static str,
+    capability: &'static str,
+) -> impl Fn(&Value, &HashMap<String, Value>) -> tera::Result<Value> + Sync + Send {
+    move |_: &Value, _: &HashMap<String, Value>| Err(denied(name, capability))
+}
+
+pub fn denied_function(
+    name: &'static str,
+    capability: &'static str,
+) -> impl Fn(&HashMap<String, Value>) -> tera::Result<Value> + Sync + Send {
+    move |_: &HashMap<String, Value>| Err(denied(name, capability))
+}
+
+pub fn denied_tester(
+    name: &'static str,
+    capability: &'static str,
+) -> impl Fn(Option<&Value>, &[Value]) -> tera::Result<bool> + Sync + Send {
+    move |_: Option<&Value>, _: &[Value]| Err(denied(name, capability))
+}
@@ -0,0 +1,284 @@
+use crate::filters;
+use crate::inners::deep_merge;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use semver::{Version, VersionReq};
+use std::collections::HashMap;
+use tera::{Result, Value};
+
+/// strings as is, null as empty and other values as json
+fn str_value(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        Value::Null => String::new(),
+        _ => value.to_string(),
+    }
+}
+
+fn string_arg<'a>(name: &str, args: &'a HashMap<String, Value>, arg: &str) -> Result<&'a str> {
+    if let Some(Value::String(value)) = args.get(arg) {
+        return Ok(value);
+    } else {
+        return Err(format!("{}: Invalid type for arg {}, expected string", name, arg).into());
+    }
+}
+
+pub fn quote(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::Null = piped_arg {
+        return Ok(Value::String(String::new()));
+    }
+    return Ok(Value::String(Value::String(str_value(piped_arg)).to_string()));
+}
+
+pub fn squote(piped_arg: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::Null = piped_arg {
+        return Ok(Value::String(String::new()));
+    }
+    return Ok(Value::String(format!("'{}'", str_value(piped_arg))));
+}
+
+/// like to_yaml without the trailing line break
+pub fn to_yaml(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::String(yaml) = filters::to_yaml(piped_arg, args)? {
+        return Ok(Value::String(yaml.strip_suffix('\n').unwrap_or(&yaml).to_owned()));
+    } else {
+        return Err("toYaml: Error serializing to yaml".into());
+    }
+}
+
+pub fn trim_prefix(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::String(value) = piped_arg {
+        let prefix = string_arg("trimPrefix", args, "prefix")?;
+        return Ok(Value::String(value.strip_prefix(prefix).unwrap_or(value).to_owned()));
+    } else {
+        return Err("trimPrefix: Invalid type, expected string".into());
+    }
+}
+
+pub fn trim_suffix(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::String(value) = piped_arg {
+        let suffix = string_arg("trimSuffix", args, "suffix")?;
+        return Ok(Value::String(value.strip_suffix(suffix).unwrap_or(value).to_owned()));
+    } else {
+        return Err("trimSuffix: Invalid type, expected string".into());
+    }
+}
+
+/// the piped value is kept over the ones of src, src is an object or a list of objects merged in order
+pub fn merge(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if !piped_arg.is_object() {
+        return Err("merge: Invalid type, expected object".into());
+    }
+    let sources = match args.get("src") {
+        Some(Value::Object(_)) => vec![args["src"].clone()],
+        Some(Value::Array(sources)) if sources.iter().all(Value::is_object) => sources.clone(),
+        _ => return Err("merge: Invalid type for arg src, expected object or list of objects".into()),
+    };
+    let mut merged = piped_arg.clone();
+    for mut source in sources {
+        deep_merge(&mut source, &merged);
+        merged = source;
+    }
+    return Ok(merged);
+}
+
+pub fn has_key(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::Object(object) = piped_arg {
+        let key = string_arg("hasKey", args, "key")?;
+        return Ok(Value::Bool(object.contains_key(key)));
+    } else {
+        return Err("hasKey: Invalid type, expected object".into());
+    }
+}
+
+/// values of key in the objects of the piped list that have it
+pub fn pluck(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    if let Value::Array(items) = piped_arg {
+        let key = string_arg("pluck", args, "key")?;
+        let values = items
+            .iter()
+            .filter_map(|item| item.as_object().and_then(|object| object.get(key)).cloned())
+            .collect();
+        return Ok(Value::Array(values));
+    } else {
+        return Err("pluck: Invalid type, expected list of objects".into());
+    }
+}
+
+/// null is false_val unless none_val is given like in ansible
+pub fn ternary(piped_arg: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    let true_val = args.get("true_val").ok_or("ternary: Missing arg true_val")?;
+    let false_val = args.get("false_val").ok_or("ternary: Missing arg false_val")?;
+    match piped_arg {
+        Value::Bool(true) => return Ok(true_val.clone()),
+        Value::Bool(false) => return Ok(false_val.clone()),
+        Value::Null => return Ok(args.get("none_val").unwrap_or(false_val).clone()),
+        _ => return Err("ternary: Invalid type, expected boolean".into()),
+    }
+}
+
+/// adds the missing minor and patch numbers to a version without the leading v
+fn pad_version(version: &str) -> String {
+    let core_len = version.find(['-', '+']).unwrap_or(version.len());
+    let (core, rest) = version.split_at(core_len);
+    let mut core = core.to_owned();
+    for _ in core.matches('.').count()..2 {
+        core.push_str(".0");
+    }
+    return core + rest;
+}
+
+/// versions can start with v and miss the minor or patch numbers like in sprig
+fn parse_version(version: &str) -> Result<Version> {
+    let version = version.trim().trim_start_matches(['v', 'V']);
+    return Version::parse(&pad_version(version))
+        .map_err(|e| format!("Error parsing version {} : {}", version, e).into());
+}
+
+/// comparators are separated by commas or spaces and can have spaces after the operator like `>= 1.2 < 2`
+fn split_comparators(alternative: &str) -> Vec<String> {
+    let mut comparators = vec![];
+    let mut op = String::new();
+    for token in alternative.split(|c: char| c == ',' || c.is_whitespace()).filter(|token| !token.is_empty()) {
+        if token.chars().all(|c| "<>=~^".contains(c)) {
+            op.push_str(token);
+        } else {
+            comparators.push(format!("{}{}", op, token));
+            op.clear();
+        }
+    }
+    if !op.is_empty() {
+        comparators.push(op);
+    }
+    return comparators;
+}
+
+/// a constraint alternative and whether it has a prerelease, like `>=1.19-0`, that allows prerelease versions
+struct Alternative {
+    req: VersionReq,
+    prerelease: bool,
+}
+
+/// constraints without operator match the exact version and `||` separates alternatives like in sprig,
+/// partial versions keep the semver meaning, `>1.2` is `>=1.3.0`, unless they have a prerelease like `1.19-0`
+fn parse_constraint(constraint: &str) -> Result<Vec<Alternative>> {
+    let mut alternatives = vec![];
+    for alternative in constraint.split("||") {
+        let mut prerelease = false;
+        let comparators: Vec<_> = split_comparators(alternative)
+            .iter()
+            .map(|comparator| {
+                let op_len = comparator.find(|c| !"<>=~^".contains(c)).unwrap_or(comparator.len());
+                let (op, version) = comparator.split_at(op_len);
+                let mut version = version.trim_start_matches(['v', 'V']).to_owned();
+                if version.contains(['-', '+']) {
+                    prerelease |= version.split('+').next().unwrap_or("").contains('-');
+                    version = pad_version(&version);
+                }
+                format!("{}{}", if op.is_empty() { "=" } else { op }, version)
+            })
+            .collect();
+        let req = VersionReq::parse(&comparators.join(", "))
+            .map_err(|e| format!("Error parsing constraint {} : {}", constraint, e))?;
+        alternatives.push(Alternative { req, prerelease });
+    }
+    return Ok(alternatives);
+}
+
+/// prerelease versions only match alternatives with a prerelease, then their major, minor and patch numbers are
+/// compared like in sprig, so `>=1.19-0` matches `1.21.3-gke.100`
+fn matches(alternative: &Alternative, version: &Version) -> bool {
+    if alternative.prerelease {
+        return alternative
+            .req
+            .matches(&Version::new(version.major, version.minor, version.patch));
+    }
+    return alternative.req.matches(version);
+}
+
+pub fn semver_compare(args: &HashMap<String, Value>) -> Result<Value> {
+    let constraint = string_arg("semverCompare", args, "constraint")?;
+    let version = string_arg("semverCompare", args, "version")?;
+    let version = parse_version(version).map_err(|e| format!("semverCompare: {}", e))?;
+    let alternatives = parse_constraint(constraint).map_err(|e| format!("semverCompare: {}", e))?;
+    return Ok(Value::Bool(alternatives.iter().any(|alternative| matches(alternative, &version))));
+}
+
+pub fn uuidv4(_: &HashMap<String, Value>) -> Result<Value> {
+    return Ok(Value::String(uuid::Uuid::new_v4().to_string()));
+}
+
+pub fn rand_alpha_num(args: &HashMap<String, Value>) -> Result<Value> {
+    let len = if let Some(Value::Number(len)) = args.get("len") {
+        len.as_u64().ok_or("randAlphaNum: Error len is not unsigned integer")? as usize
+    } else {
+        return Err("randAlphaNum: Invalid type for arg len, expected number".into());
+    };
+    let random: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect();
+    return Ok(Value::String(random));
+}
+
+/// object with the args as entries
+pub fn dict(args: &HashMap<String, Value>) -> Result<Value> {
+    return Ok(Value::Object(
+        args.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compare(constraint: &str, version: &str) -> bool {
+        let args: HashMap<String, Value> = [("constraint", constraint), ("version", version)]
+            .iter()
+            .map(|(k, v)| (k.to_string(), Value::from(*v)))
+            .collect();
+        return semver_compare(&args).unwrap().as_bool().unwrap();
+    }
+
+    #[test]
+    fn partial_versions_with_prerelease_are_padded() {
+        assert!(compare(">=1.19-0", "1.21.3"));
+        assert!(compare(">=1.19-0", "v1.19.0"));
+        assert!(!compare(">=1.19-0", "1.18.9"));
+    }
+
+    #[test]
+    fn prerelease_constraints_match_the_version_core() {
+        assert!(compare(">=1.19.0-0", "v1.21.3-gke.100"));
+        assert!(compare(">=1.19-0", "v1.21.3-gke.100"));
+        assert!(!compare(">=1.22.0-0", "v1.21.3-gke.100"));
+        assert!(!compare(">=1.19.0", "v1.21.3-gke.100"));
+    }
+
+    #[test]
+    fn comparators_can_be_separated_by_spaces() {
+        assert!(compare(">= 1.2 < 2", "1.5.0"));
+        assert!(!compare(">= 1.2 < 2", "2.0.0"));
+        assert!(compare(">=1.2 <2", "1.2.0"));
+        assert!(compare(">=1.2, <2", "1.9.9"));
+    }
+
+    #[test]
+    fn alternatives_and_partial_versions() {
+        assert!(compare("1.2.3 || >=2", "1.2.3"));
+        assert!(compare("1.2.3 || >=2", "v2.1"));
+        assert!(!compare("1.2.3 || >=2", "1.2.4"));
+        assert!(!compare(">1.2", "1.2.5"));
+        assert!(compare(">1.2", "1.3.0"));
+    }
+
+    #[test]
+    fn invalid_constraints_are_errors() {
+        let args: HashMap<String, Value> = [("constraint", ">="), ("version", "1.0.0")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), Value::from(*v)))
+            .collect();
+        assert!(semver_compare(&args).is_err());
+    }
+}
@@ -1,9 +1,12 @@
-use std::path::Path;
+use crate::sandbox;
+use regex::Regex;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
 use tera::{Result, Value};
 
 pub fn is_file(value: Option<&Value>, _: &[Value]) -> Result<bool> {
     if let Some(Value::String(path)) = value {
-        let path = Path::new(&path);
+        let path = sandbox::check_path("is_file", path)?;
         return Ok(path.is_file());
     } else {
         return Err("is_file: Invalid type, expected string".into());
@@ -12,9 +15,69 @@ pub fn is_file(value: Option<&Value>, _: &[Value]) -> Result<bool> {
 
 pub fn is_directory(value: Option<&Value>, _: &[Value]) -> Result<bool> {
     if let Some(Value::String(path)) = value {
-        let path = Path::new(&path);
+        let path = sandbox::check_path("is_directory", path)?;
         return Ok(path.is_dir());
     } else {
         return Err("is_directory: Invalid type, expected string".into());
     }
 }
+
+pub fn matches_regex(value: Option<&Value>, args: &[Value]) -> Result<bool> {
+    let pattern = if let Some(Value::String(pattern)) = args.first() {
+        pattern
+    } else {
+        return Err("matches_regex: Invalid type for pattern, expected string".into());
+    };
+    let regex = Regex::new(pattern).map_err(|e| format!("matches_regex: error in regex {} : {}", pattern, e))?;
+    if let Some(Value::String(data)) = value {
+        return Ok(regex.is_match(data));
+    } else {
+        return Err("matches_regex: Invalid type, expected string".into());
+    }
+}
+
+pub fn exists(value: Option<&Value>, _: &[Value]) -> Result<bool> {
+    if let Some(Value::String(path)) = value {
+        let path = sandbox::check_path("exists", path)?;
+        return Ok(path.exists());
+    } else {
+        return Err("exists: Invalid type, expected string".into());
+    }
+}
+
+/// true for symlinks even if their target doesn't exist
+pub fn is_symlink(value: Option<&Value>, _: &[Value]) -> Result<bool> {
+    if let Some(Value::String(path)) = value {
+        let path = sandbox::check_path("is_symlink", path)?;
+        return Ok(fs::symlink_metadata(path)
+            .map(|metadata| metadata.file_type().is_symlink())
+            .unwrap_or(false));
+    } else {
+        return Err("is_symlink: Invalid type, expected string".into());
+    }
+}
+
+/// true for files with any execute permission bit
+pub fn is_executable(value: Option<&Value>, _: &[Value]) -> Result<bool> {
+    if let Some(Value::String(path)) = value {
+        let path = sandbox::check_path("is_executable", path)?;
+        return Ok(fs::metadata(path)
+            .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false));
+    } else {
+        return Err("is_executable: Invalid type, expected string".into());
+    }
+}
+
+/// true if the file can be opened or the dir listed by the current user
+pub fn is_readable(value: Option<&Value>, _: &[Value]) -> Result<bool> {
+    if let Some(Value::String(path)) = value {
+        let path = sandbox::check_path("is_readable", path)?;
+        if path.is_dir() {
+            return Ok(fs::read_dir(path).is_ok());
+        }
+        return Ok(fs::File::open(path).is_ok());
+    } else {
+        return Err("is_readable: Invalid type, expected string".into());
+    }
+}